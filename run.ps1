@@ -4,7 +4,7 @@ $buildOutput = @()
 $buildGood = 0
 $bindingGood = 0
 
-$rustBuildOutput = cargo build --target wasm32-unknown-unknown --release 2>&1
+$rustBuildOutput = cargo build --target wasm32-unknown-unknown --release --no-default-features --features web 2>&1
 $exitCode = $LASTEXITCODE
 
 if ($exitCode -eq 0)
@@ -19,7 +19,7 @@ $buildOutput += $rustBuildOutput
 
 if ($buildGood -eq 1)
 {
-  $bindingGen = wasm-bindgen --target web --no-typescript --out-dir ./www/pkg ./target/wasm32-unknown-unknown/release/gpudemo.wasm
+  $bindingGen = wasm-bindgen --target web --out-dir ./www/pkg ./target/wasm32-unknown-unknown/release/gpudemo.wasm
   $exitCode = $LASTEXITCODE
 
   if ($exitCode -eq 0)
@@ -0,0 +1,182 @@
+use nalgebra::{Rotation3, Unit, Vector3};
+use serde::{Deserialize, Serialize};
+
+use crate::scene::CameraDesc;
+use crate::settings::RenderSettings;
+
+type Vector3f = Vector3<f32>;
+
+/// How the camera moves over the timeline
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub enum CameraTrack {
+    // The camera of the scene, all along
+    #[default]
+    Still,
+    // Circle the scene camera around `look_at` about `up`, `turns` times over the duration
+    Turntable { turns: f32 },
+    // Linear between the keys, ordered by time. Before the first and after the last key the
+    // camera holds still
+    Keyframes(Vec<CameraKey>),
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct CameraKey {
+    // In seconds
+    pub time: f32,
+    pub camera: CameraDesc,
+}
+
+/// Timeline of a scene file, `settings.time` picks the moment that is rendered:
+/// `{ "fps": 30, "duration": 4, "camera": { "turntable": { "turns": 1 } } }`
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AnimationDesc {
+    // Frames per second of a sequence
+    pub fps: f32,
+    // In seconds
+    pub duration: f32,
+    pub camera: CameraTrack,
+}
+
+impl Default for AnimationDesc {
+    fn default() -> Self {
+        Self {
+            fps: 30.0,
+            duration: 4.0,
+            camera: CameraTrack::Still,
+        }
+    }
+}
+
+impl AnimationDesc {
+    /// Whether anything changes over time
+    pub fn is_animated(&self) -> bool {
+        self.camera != CameraTrack::Still
+    }
+
+    /// Frames of a sequence over the duration, at least one
+    pub fn frame_count(&self) -> u32 {
+        ((self.duration * self.fps).round() as u32).max(1)
+    }
+
+    /// Time of a frame in seconds
+    pub fn time(&self, frame: u32) -> f32 {
+        frame as f32 / self.fps
+    }
+
+    /// `settings` for rendering `frame` of a sequence
+    pub fn frame_settings(&self, settings: &RenderSettings, frame: u32) -> RenderSettings {
+        RenderSettings {
+            frame,
+            time: self.time(frame),
+            ..*settings
+        }
+    }
+
+    /// The camera at `time`, `camera` is the one of the scene file
+    pub fn camera_at(&self, camera: &CameraDesc, time: f32) -> CameraDesc {
+        match &self.camera {
+            CameraTrack::Still => *camera,
+            CameraTrack::Turntable { turns } => {
+                let progress = if self.duration > 0.0 {
+                    time / self.duration
+                } else {
+                    0.0
+                };
+                turn(camera, turns * progress * std::f32::consts::TAU)
+            }
+            CameraTrack::Keyframes(keys) => keyframe(keys, time).unwrap_or(*camera),
+        }
+    }
+}
+
+// Rotate the camera position around `look_at` about the up vector
+fn turn(camera: &CameraDesc, angle: f32) -> CameraDesc {
+    let Some(up) = Unit::try_new(Vector3f::from(camera.up), f32::EPSILON) else {
+        return *camera;
+    };
+    let target = Vector3f::from(camera.look_at);
+    let offset = Rotation3::from_axis_angle(&up, angle) * (Vector3f::from(camera.position) - target);
+    CameraDesc {
+        position: (target + offset).into(),
+        ..*camera
+    }
+}
+
+fn keyframe(keys: &[CameraKey], time: f32) -> Option<CameraDesc> {
+    let next = keys.partition_point(|key| key.time <= time);
+    let (a, b) = match (next.checked_sub(1).map(|i| &keys[i]), keys.get(next)) {
+        (Some(a), Some(b)) => (a, b),
+        (Some(key), None) | (None, Some(key)) => return Some(key.camera),
+        (None, None) => return None,
+    };
+    let t = (time - a.time) / (b.time - a.time);
+    let lerp = |a: [f32; 3], b: [f32; 3]| [0, 1, 2].map(|i| a[i] + (b[i] - a[i]) * t);
+    Some(CameraDesc {
+        position: lerp(a.camera.position, b.camera.position),
+        look_at: lerp(a.camera.look_at, b.camera.look_at),
+        up: lerp(a.camera.up, b.camera.up),
+        focal_length: a.camera.focal_length + (b.camera.focal_length - a.camera.focal_length) * t,
+    })
+}
+
+/// The animation of the loaded scene and the camera it starts from, the renderers pose the
+/// camera at `settings.time` with it
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Timeline {
+    pub animation: AnimationDesc,
+    pub camera: CameraDesc,
+}
+
+impl Timeline {
+    /// The camera at `time`, None when the camera doesn't move
+    pub fn pose(&self, time: f32) -> Option<CameraDesc> {
+        self.animation
+            .is_animated()
+            .then(|| self.animation.camera_at(&self.camera, time))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn turntable_and_keyframes_follow_the_timeline() {
+        let camera = CameraDesc {
+            position: [0.0, 0.0, -10.0],
+            look_at: [0.0; 3],
+            up: [0.0, 1.0, 0.0],
+            focal_length: 35.0,
+        };
+        let turntable = AnimationDesc {
+            fps: 10.0,
+            duration: 2.0,
+            camera: CameraTrack::Turntable { turns: 1.0 },
+        };
+        assert_eq!(turntable.frame_count(), 20);
+        assert_eq!(turntable.frame_settings(&RenderSettings::default(), 5).time, 0.5);
+        // Half a turn halfway through
+        let half = turntable.camera_at(&camera, 1.0);
+        assert!((Vector3f::from(half.position) - Vector3f::new(0.0, 0.0, 10.0)).norm() < 1e-4);
+
+        let moved = CameraDesc {
+            position: [0.0, 0.0, -20.0],
+            focal_length: 50.0,
+            ..camera
+        };
+        let keyframes = AnimationDesc {
+            camera: CameraTrack::Keyframes(vec![
+                CameraKey { time: 1.0, camera },
+                CameraKey { time: 3.0, camera: moved },
+            ]),
+            ..Default::default()
+        };
+        assert_eq!(keyframes.camera_at(&moved, 0.0), camera);
+        let between = keyframes.camera_at(&camera, 2.0);
+        assert_eq!((between.position, between.focal_length), ([0.0, 0.0, -15.0], 42.5));
+        assert_eq!(keyframes.camera_at(&camera, 9.0), moved);
+    }
+}
@@ -0,0 +1,563 @@
+//! JavaScript API of the web build:
+//! `const demo = await GpuDemo.create(); demo.setSettings({ max_bounces: 8 });`
+//! Plain objects follow the scene file format, see `SceneDesc`
+
+use std::{cell::RefCell, rc::Rc};
+
+use serde::{de::DeserializeOwned, Serialize};
+use wasm_bindgen::prelude::*;
+use winit::{
+    event_loop::{EventLoop, EventLoopProxy},
+    platform::web::{EventLoopExtWebSys, WindowExtWebSys},
+};
+
+use crate::app::{App, AppEvent, ViewerRequest};
+use crate::scene::{self, CameraDesc, MaterialDesc, SceneDesc};
+use crate::settings::{RenderSettings, Specialization};
+use crate::sphere::Material;
+use crate::viewer::{DynamicResolution, RenderPolicy, Viewer};
+use crate::world::{MaterialHandle, Scene, SphereHandle};
+
+#[wasm_bindgen(typescript_custom_section)]
+const TYPES: &'static str = r#"
+export type Vec3 = [number, number, number];
+
+/**
+ * When new samples are drawn: every frame, until the frame holds `converge` samples, or once
+ * per change and `requestFrame()`. Nothing is drawn while the page is hidden
+ */
+export type RenderPolicy = "continuous" | { converge: number } | "on_demand";
+
+/** A material of a scene file */
+export interface Material {
+    albedo: Vec3;
+    kind?: "lambert" | "metal";
+    /** Metal only, in [0, 1] */
+    fuzz?: number;
+}
+
+/** Pinhole camera, left out fields keep their value */
+export interface Camera {
+    position?: Vec3;
+    look_at?: Vec3;
+    up?: Vec3;
+    /** In mm, on a 24 mm high sensor */
+    focal_length?: number;
+}
+
+/** Sampling parameters, left out fields keep their value */
+export interface RenderSettings {
+    spp?: number;
+    max_bounces?: number;
+    seed?: number;
+    deterministic?: boolean;
+    /** Frame of a sequence, mixed into the seed */
+    frame?: number;
+    /** In seconds, poses the camera of an animated scene */
+    time?: number;
+    /** Closest and farthest hit of a camera ray */
+    tmin?: number;
+    tmax?: number;
+    /** Closest hit of a bounced ray, against self intersection */
+    bounce_tmin?: number;
+    /** Largest color channel of a sample, 0 is off */
+    clamp?: number;
+    debug?: "off" | "normal" | "albedo" | "bounces";
+}
+
+/** Compile time variants of the passes, left out fields keep their value */
+export interface Specialization {
+    workgroup_size?: [number, number];
+    /** Bake `max_bounces` into the shade pass */
+    fixed_bounces?: boolean;
+}
+"#;
+
+fn from_js<T: DeserializeOwned>(value: &JsValue) -> Result<T, JsError> {
+    let json = js_sys::JSON::stringify(value)
+        .map_err(|_| JsError::new("value can't be converted to JSON"))?;
+    Ok(serde_json::from_str(&String::from(json))?)
+}
+
+fn to_js<T: Serialize>(value: &T) -> JsValue {
+    let json = serde_json::to_string(value).expect("Serializable value");
+    js_sys::JSON::parse(&json).expect("Valid JSON")
+}
+
+// `current` with the fields of the object `patch` replaced
+fn merge<T: Serialize + DeserializeOwned>(current: &T, patch: &JsValue) -> Result<T, JsError> {
+    let mut value = serde_json::to_value(current)?;
+    let serde_json::Value::Object(patch) = from_js(patch)? else {
+        return Err(JsError::new("expected an object"));
+    };
+    value
+        .as_object_mut()
+        .expect("Struct serializes to an object")
+        .extend(patch);
+    Ok(serde_json::from_value(value)?)
+}
+
+thread_local! {
+    // A page runs a single event loop, started by the first viewer
+    static EVENT_PROXY: RefCell<Option<EventLoopProxy<AppEvent>>> = const { RefCell::new(None) };
+}
+
+fn event_proxy() -> Result<EventLoopProxy<AppEvent>, JsError> {
+    EVENT_PROXY.with_borrow_mut(|proxy| {
+        if let Some(proxy) = proxy {
+            return Ok(proxy.clone());
+        }
+        let event_loop = EventLoop::<AppEvent>::with_user_event()
+            .build()
+            .map_err(|e| JsError::new(&e.to_string()))?;
+        let new_proxy = event_loop.create_proxy();
+        event_loop.spawn_app(App::new(new_proxy.clone()));
+        Ok(proxy.insert(new_proxy).clone())
+    })
+}
+
+// A canvas element, or the id of one
+fn find_canvas(canvas: JsValue) -> Result<web_sys::HtmlCanvasElement, JsError> {
+    let element = match canvas.as_string() {
+        Some(id) => web_sys::window()
+            .and_then(|window| window.document())
+            .and_then(|document| document.get_element_by_id(&id))
+            .ok_or_else(|| JsError::new(&format!("no element with id '{id}'")))?
+            .into(),
+        None => canvas,
+    };
+    element
+        .dyn_into()
+        .map_err(|_| JsError::new("expected a canvas element"))
+}
+
+fn vec3(v: &[f32]) -> Result<[f32; 3], JsError> {
+    v.try_into()
+        .map_err(|_| JsError::new(&format!("expected 3 numbers, got {}", v.len())))
+}
+
+/// A viewer on the page, created by `GpuDemo.create`
+#[wasm_bindgen]
+pub struct GpuDemo {
+    state: Rc<RefCell<Option<Viewer>>>,
+}
+
+#[wasm_bindgen]
+impl GpuDemo {
+    /// Show the scene at `scene_url`, the `?scene=` of the page or the demo scene.
+    /// Draws into `canvas`, an element or its id, following its CSS size, or into a new
+    /// canvas filling the page. Every viewer has its own scene, camera and settings
+    pub async fn create(
+        scene_url: Option<String>,
+        #[wasm_bindgen(unchecked_param_type = "HTMLCanvasElement | string")] canvas: Option<JsValue>,
+    ) -> Result<GpuDemo, JsError> {
+        let mut request = ViewerRequest::new(scene_url.or_else(crate::web::scene_url));
+        if let Some(canvas) = canvas {
+            request = request.with_canvas(find_canvas(canvas)?);
+        }
+        let ready = request.on_ready();
+        let state = request.state();
+        event_proxy()?
+            .send_event(AppEvent::AddViewer(request))
+            .map_err(|_| JsError::new("the event loop is gone"))?;
+
+        ready
+            .await
+            .map_err(|_| JsError::new("viewer initialisation failed"))?;
+        Ok(GpuDemo { state })
+    }
+
+    fn with<T>(&self, f: impl FnOnce(&Viewer) -> Result<T, JsError>) -> Result<T, JsError> {
+        let state = self
+            .state
+            .try_borrow()
+            .map_err(|_| JsError::new("viewer is busy"))?;
+        f(state.as_ref().ok_or_else(|| JsError::new("viewer is gone"))?)
+    }
+
+    // Calls change the viewer outside of the event loop, which may be asleep
+    fn with_mut<T>(
+        &self,
+        f: impl FnOnce(&mut Viewer) -> Result<T, JsError>,
+    ) -> Result<T, JsError> {
+        let mut state = self
+            .state
+            .try_borrow_mut()
+            .map_err(|_| JsError::new("viewer is busy"))?;
+        let viewer = state
+            .as_mut()
+            .ok_or_else(|| JsError::new("viewer is gone"))?;
+        let result = f(viewer);
+        viewer.window().request_redraw();
+        result
+    }
+
+    /// Replace the scene with a scene file
+    #[wasm_bindgen(js_name = loadScene)]
+    pub fn load_scene(&self, json: &str) -> Result<(), JsError> {
+        let scene = SceneDesc::from_json(json)?;
+        self.with_mut(|viewer| Ok(viewer.load_scene(&scene)?))
+    }
+
+    /// Fetch a scene file and show it
+    #[wasm_bindgen(js_name = fetchScene, unchecked_return_type = "Promise<void>")]
+    pub fn fetch_scene(&self, url: String) -> js_sys::Promise {
+        let state = self.state.clone();
+        wasm_bindgen_futures::future_to_promise(async move {
+            let json = crate::web::fetch_text(&url).await?;
+            GpuDemo { state }.load_scene(&json)?;
+            Ok(JsValue::UNDEFINED)
+        })
+    }
+
+    /// Add a material, returns its id
+    #[wasm_bindgen(js_name = addMaterial)]
+    pub fn add_material(
+        &self,
+        #[wasm_bindgen(unchecked_param_type = "Material")] material: JsValue,
+    ) -> Result<u32, JsError> {
+        let desc: MaterialDesc = from_js(&material)?;
+        self.with_mut(|viewer| {
+            let handle = viewer
+                .renderer
+                .scene_mut()
+                .add_material(Material::from(&desc))?;
+            Ok(handle.index() as u32)
+        })
+    }
+
+    /// Change the material `id`, every sphere using it follows
+    #[wasm_bindgen(js_name = setMaterial)]
+    pub fn set_material(
+        &self,
+        id: u32,
+        #[wasm_bindgen(unchecked_param_type = "Material")] material: JsValue,
+    ) -> Result<(), JsError> {
+        let desc: MaterialDesc = from_js(&material)?;
+        self.with_mut(|viewer| {
+            let scene = viewer.renderer.scene_mut();
+            let handle = material_handle(scene, id)?;
+            Ok(scene.update_material(handle, Material::from(&desc))?)
+        })
+    }
+
+    /// Number of materials, their ids count up from 0 in the order of the scene file
+    #[wasm_bindgen(js_name = materialCount)]
+    pub fn material_count(&self) -> Result<u32, JsError> {
+        self.with(|viewer| Ok(viewer.renderer.scene().materials().len() as u32))
+    }
+
+    /// Add a sphere, returns its id
+    #[wasm_bindgen(js_name = addSphere)]
+    pub fn add_sphere(&self, position: &[f32], radius: f32, material: u32) -> Result<u32, JsError> {
+        let position = vec3(position)?;
+        self.with_mut(|viewer| {
+            let scene = viewer.renderer.scene_mut();
+            let material = material_handle(scene, material)?;
+            Ok(scene.add_sphere(position, radius, material)?.id())
+        })
+    }
+
+    #[wasm_bindgen(js_name = removeSphere)]
+    pub fn remove_sphere(&self, id: u32) -> Result<(), JsError> {
+        self.with_mut(|viewer| {
+            Ok(viewer
+                .renderer
+                .scene_mut()
+                .remove(SphereHandle::from_id(id))?)
+        })
+    }
+
+    /// Move and scale the sphere `id`
+    #[wasm_bindgen(js_name = setSphereTransform)]
+    pub fn set_sphere_transform(&self, id: u32, position: &[f32], radius: f32) -> Result<(), JsError> {
+        let position = vec3(position)?;
+        self.with_mut(|viewer| {
+            Ok(viewer
+                .renderer
+                .scene_mut()
+                .set_transform(SphereHandle::from_id(id), position, radius)?)
+        })
+    }
+
+    #[wasm_bindgen(js_name = setSphereMaterial)]
+    pub fn set_sphere_material(&self, id: u32, material: u32) -> Result<(), JsError> {
+        self.with_mut(|viewer| {
+            let scene = viewer.renderer.scene_mut();
+            let material = material_handle(scene, material)?;
+            Ok(scene.set_material(SphereHandle::from_id(id), material)?)
+        })
+    }
+
+    /// Ids of the spheres
+    pub fn spheres(&self) -> Result<Vec<u32>, JsError> {
+        self.with(|viewer| {
+            Ok(viewer
+                .renderer
+                .scene()
+                .sphere_handles()
+                .iter()
+                .map(|handle| handle.id())
+                .collect())
+        })
+    }
+
+    #[wasm_bindgen(unchecked_return_type = "Required<Camera>")]
+    pub fn camera(&self) -> Result<JsValue, JsError> {
+        self.with(|viewer| Ok(to_js(viewer.renderer.view())))
+    }
+
+    /// Move the camera, fields left out keep their value
+    #[wasm_bindgen(js_name = setCamera)]
+    pub fn set_camera(
+        &self,
+        #[wasm_bindgen(unchecked_param_type = "Camera")] camera: JsValue,
+    ) -> Result<(), JsError> {
+        self.with_mut(|viewer| {
+            let view: CameraDesc = merge(viewer.renderer.view(), &camera)?;
+            scene::validate_camera(&view)?;
+            viewer.renderer.set_view(view);
+            Ok(())
+        })
+    }
+
+    #[wasm_bindgen(unchecked_return_type = "Required<RenderSettings>")]
+    pub fn settings(&self) -> Result<JsValue, JsError> {
+        self.with(|viewer| Ok(to_js(viewer.renderer.settings())))
+    }
+
+    /// Change the sampling and tracing parameters, fields left out keep their value.
+    /// Takes effect with the next frame, only `deterministic` and `max_bounces` with
+    /// `fixed_bounces` switch pipelines
+    #[wasm_bindgen(js_name = setSettings)]
+    pub fn set_settings(
+        &self,
+        #[wasm_bindgen(unchecked_param_type = "RenderSettings")] settings: JsValue,
+    ) -> Result<(), JsError> {
+        self.with_mut(|viewer| {
+            let settings: RenderSettings = merge(viewer.renderer.settings(), &settings)?;
+            scene::validate_settings(&settings)?;
+            viewer.renderer.set_settings(settings);
+            Ok(())
+        })
+    }
+
+    #[wasm_bindgen(unchecked_return_type = "Required<Specialization>")]
+    pub fn specialization(&self) -> Result<JsValue, JsError> {
+        self.with(|viewer| Ok(to_js(viewer.renderer.specialization())))
+    }
+
+    /// Switch the passes to other pipelines, the first use of a variant builds them
+    #[wasm_bindgen(js_name = setSpecialization)]
+    pub fn set_specialization(
+        &self,
+        #[wasm_bindgen(unchecked_param_type = "Specialization")] specialization: JsValue,
+    ) -> Result<(), JsError> {
+        self.with_mut(|viewer| {
+            let specialization: Specialization =
+                merge(viewer.renderer.specialization(), &specialization)?;
+            if !viewer.renderer.set_specialization(specialization) {
+                return Err(JsError::new("could not build the pipelines, see the console"));
+            }
+            Ok(())
+        })
+    }
+
+    #[wasm_bindgen(js_name = renderPolicy, unchecked_return_type = "RenderPolicy")]
+    pub fn render_policy(&self) -> Result<JsValue, JsError> {
+        self.with(|viewer| Ok(to_js(&viewer.render_policy())))
+    }
+
+    #[wasm_bindgen(js_name = setRenderPolicy)]
+    pub fn set_render_policy(
+        &self,
+        #[wasm_bindgen(unchecked_param_type = "RenderPolicy")] policy: JsValue,
+    ) -> Result<(), JsError> {
+        let policy: RenderPolicy = from_js(&policy)?;
+        self.with_mut(|viewer| {
+            viewer.set_render_policy(policy);
+            Ok(())
+        })
+    }
+
+    /// Draw one more sample with the `"on_demand"` policy
+    #[wasm_bindgen(js_name = requestFrame)]
+    pub fn request_frame(&self) -> Result<(), JsError> {
+        self.with_mut(|viewer| {
+            viewer.request_frame();
+            Ok(())
+        })
+    }
+
+    /// Stop rendering, the canvas keeps the last frame
+    pub fn pause(&self) -> Result<(), JsError> {
+        self.with_mut(|viewer| {
+            viewer.paused = true;
+            Ok(())
+        })
+    }
+
+    pub fn resume(&self) -> Result<(), JsError> {
+        self.with_mut(|viewer| {
+            viewer.paused = false;
+            Ok(())
+        })
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn paused(&self) -> Result<bool, JsError> {
+        self.with(|viewer| Ok(viewer.paused))
+    }
+
+    /// Frame size relative to the canvas size in device pixels
+    #[wasm_bindgen(getter, js_name = renderScale)]
+    pub fn render_scale(&self) -> Result<f32, JsError> {
+        self.with(|viewer| Ok(viewer.render_scale()))
+    }
+
+    /// Render at `scale` times the device pixels of the canvas, from 0.25 to 2. Lower is
+    /// faster and blurrier, the frame is upscaled to the canvas
+    #[wasm_bindgen(setter, js_name = renderScale)]
+    pub fn set_render_scale(&self, scale: f32) -> Result<(), JsError> {
+        if !scale.is_finite() {
+            return Err(JsError::new("renderScale: not a finite number"));
+        }
+        self.with_mut(|viewer| {
+            viewer.set_render_scale(scale);
+            Ok(())
+        })
+    }
+
+    /// While the camera is dragged, render one sample per frame at `scale` times the render
+    /// scale, back to full resolution `settle_ms` after the last input. A scale of 1 only
+    /// restarts the accumulation
+    #[wasm_bindgen(js_name = setDynamicResolution)]
+    pub fn set_dynamic_resolution(&self, scale: f32, settle_ms: u32) -> Result<(), JsError> {
+        if !(scale > 0.0 && scale <= 1.0) {
+            return Err(JsError::new("scale: must be in (0, 1]"));
+        }
+        self.with_mut(|viewer| {
+            viewer.set_dynamic_resolution(DynamicResolution {
+                scale,
+                settle: web_time::Duration::from_millis(settle_ms.into()),
+            });
+            Ok(())
+        })
+    }
+
+    /// Size of the frame in pixels, and of the screenshots
+    #[wasm_bindgen(getter)]
+    pub fn width(&self) -> Result<u32, JsError> {
+        self.with(|viewer| Ok(viewer.renderer.size().0))
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn height(&self) -> Result<u32, JsError> {
+        self.with(|viewer| Ok(viewer.renderer.size().1))
+    }
+
+    /// Samples accumulated in the current frame
+    #[wasm_bindgen(js_name = sampleCount)]
+    pub fn sample_count(&self) -> Result<u32, JsError> {
+        self.with(|viewer| Ok(viewer.renderer.sample_count()))
+    }
+
+    /// The current frame as sRGB RGBA8 pixels, `width * 4` bytes per row
+    #[wasm_bindgen(unchecked_return_type = "Promise<Uint8Array>")]
+    pub fn screenshot(&self) -> Result<js_sys::Promise, JsError> {
+        let frame = self.with(|viewer| {
+            if viewer.renderer.frame_texture().is_none() {
+                return Err(JsError::new("nothing rendered yet"));
+            }
+            Ok(viewer.renderer.request_frame())
+        })?;
+        Ok(wasm_bindgen_futures::future_to_promise(async move {
+            let image = frame
+                .await
+                .map_err(|e| JsError::new(&format!("could not read the frame: {e}")))?;
+            Ok(js_sys::Uint8Array::from(image.data.as_slice()).into())
+        }))
+    }
+
+    /// Frames of the scene animation, at its fps over its duration
+    #[wasm_bindgen(getter, js_name = frameCount)]
+    pub fn frame_count(&self) -> Result<u32, JsError> {
+        self.with(|viewer| Ok(viewer.renderer.animation().frame_count()))
+    }
+
+    /// Render every frame of the scene animation to `spp` samples and record them into a
+    /// WebM video. The recording waits while the page is hidden, the settings and render
+    /// policy are restored at the end, a paused viewer is resumed for the recording
+    #[wasm_bindgen(js_name = recordWebm, unchecked_return_type = "Promise<Blob>")]
+    pub fn record_webm(&self) -> js_sys::Promise {
+        let state = self.state.clone();
+        wasm_bindgen_futures::future_to_promise(async move {
+            let demo = GpuDemo { state };
+            let (canvas, animation, settings, policy, paused) = demo.with_mut(|viewer| {
+                let canvas = viewer
+                    .window()
+                    .canvas()
+                    .ok_or_else(|| JsError::new("viewer has no canvas"))?;
+                let animation = viewer.renderer.animation().clone();
+                let settings = *viewer.renderer.settings();
+                let restore = (viewer.render_policy(), viewer.paused);
+                viewer.set_render_policy(RenderPolicy::Converge(settings.spp));
+                viewer.paused = false;
+                Ok((canvas, animation, settings, restore.0, restore.1))
+            })?;
+
+            let recorder = crate::web::CanvasRecorder::new(&canvas, animation.fps);
+            let recorded = async {
+                let recorder = recorder?;
+                for frame in 0..animation.frame_count() {
+                    let converged = demo.with_mut(|viewer| {
+                        viewer.renderer.set_settings(animation.frame_settings(&settings, frame));
+                        Ok(viewer.on_converged())
+                    })?;
+                    converged
+                        .await
+                        .map_err(|_| JsError::new("viewer is gone"))?;
+                    recorder.add_frame().await?;
+                }
+                recorder.finish().await
+            }
+            .await;
+
+            demo.with_mut(|viewer| {
+                viewer.set_render_policy(policy);
+                viewer.paused = paused;
+                viewer.renderer.set_settings(settings);
+                Ok(())
+            })?;
+            Ok(recorded?.into())
+        })
+    }
+
+    /// The current frame as a PNG file, with the scene and camera in a tEXt chunk
+    #[wasm_bindgen(js_name = screenshotPng, unchecked_return_type = "Promise<Uint8Array>")]
+    pub fn screenshot_png(&self) -> Result<js_sys::Promise, JsError> {
+        let png = self.with(|viewer| Ok(viewer.screenshot_png()))?;
+        Ok(wasm_bindgen_futures::future_to_promise(async move {
+            let png = png.await.map_err(|e| JsError::new(&e.to_string()))?;
+            Ok(js_sys::Uint8Array::from(png.as_slice()).into())
+        }))
+    }
+
+    /// Let the browser download the `screenshotPng()` file, as gpudemo.png by default
+    #[wasm_bindgen(js_name = downloadPng, unchecked_return_type = "Promise<void>")]
+    pub fn download_png(&self, filename: Option<String>) -> Result<js_sys::Promise, JsError> {
+        let png = self.with(|viewer| Ok(viewer.screenshot_png()))?;
+        Ok(wasm_bindgen_futures::future_to_promise(async move {
+            let png = png.await.map_err(|e| JsError::new(&e.to_string()))?;
+            let filename = filename.as_deref().unwrap_or("gpudemo.png");
+            crate::web::download_png(filename, &png)?;
+            Ok(JsValue::UNDEFINED)
+        }))
+    }
+}
+
+fn material_handle(scene: &Scene, id: u32) -> Result<MaterialHandle, JsError> {
+    scene
+        .material_handle(id as usize)
+        .ok_or_else(|| JsError::new(&format!("material {id}: no such material")))
+}
@@ -0,0 +1,598 @@
+use std::{sync::Arc, rc::Rc, cell::RefCell, collections::HashSet};
+
+use futures_channel::oneshot;
+
+use crate::viewer::Viewer;
+
+use winit::{
+    application::ApplicationHandler,
+    event::*,
+    event_loop::{ActiveEventLoop, EventLoopProxy},
+    window::{Window, WindowId},
+};
+
+// Name of the PNG saved by the P key, downloaded on the web and written to the working
+// directory on native
+const SCREENSHOT_NAME: &str = "gpudemo.png";
+
+/// Window application shared by the web and the native entry points
+pub enum AppEvent {
+    InitStateDone {
+        window: Arc<Window>,
+        // Size to ask for, None to keep the one the page gave the canvas
+        size: Option<winit::dpi::Size>,
+    },
+    // Open one more viewer, the web API mounts them one by one
+    #[cfg_attr(not(target_arch = "wasm32"), allow(dead_code))]
+    AddViewer(ViewerRequest),
+    // Shader files changed, (module, source) pairs
+    #[cfg_attr(not(all(debug_assertions, target_arch = "wasm32")), allow(dead_code))]
+    ShadersChanged(Vec<(String, String)>),
+}
+
+/// A viewer to open and where to show it
+pub struct ViewerRequest {
+    // Scene to show, a URL on the web and a path on native, the demo scene when None
+    scene_source: Option<String>,
+    // Existing canvas to draw into instead of a new one appended to the page
+    #[cfg(target_arch = "wasm32")]
+    canvas: Option<web_sys::HtmlCanvasElement>,
+    render_scale: f32,
+    state: Rc<RefCell<Option<Viewer>>>,
+    // Told once the viewer exists
+    ready: Option<oneshot::Sender<()>>,
+}
+
+impl ViewerRequest {
+    pub fn new(scene_source: Option<String>) -> Self {
+        Self {
+            scene_source,
+            #[cfg(target_arch = "wasm32")]
+            canvas: None,
+            render_scale: 1.0,
+            state: Rc::new(RefCell::new(None)),
+            ready: None,
+        }
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    pub fn with_canvas(mut self, canvas: web_sys::HtmlCanvasElement) -> Self {
+        self.canvas = Some(canvas);
+        self
+    }
+
+    /// See `Viewer::set_render_scale`
+    #[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+    pub fn with_render_scale(mut self, scale: f32) -> Self {
+        self.render_scale = scale;
+        self
+    }
+
+    /// The viewer, None until it is initialised
+    #[cfg_attr(not(target_arch = "wasm32"), allow(dead_code))]
+    pub fn state(&self) -> Rc<RefCell<Option<Viewer>>> {
+        self.state.clone()
+    }
+
+    /// Resolves once the viewer is initialised and its scene loaded
+    #[cfg_attr(not(target_arch = "wasm32"), allow(dead_code))]
+    pub fn on_ready(&mut self) -> oneshot::Receiver<()> {
+        let (sender, receiver) = oneshot::channel();
+        self.ready = Some(sender);
+        receiver
+    }
+}
+
+// An open viewer
+struct Slot {
+    window_id: WindowId,
+    state: Rc<RefCell<Option<Viewer>>>,
+    surface_configured: bool,
+    // Shaders were reloaded before the viewer had pipelines to rebuild
+    stale_shaders: bool,
+}
+
+pub struct App {
+    viewers: Vec<Slot>,
+    // Requests waiting for the event loop to resume
+    pending: Vec<ViewerRequest>,
+    resumed: bool,
+    event_proxy: Rc<EventLoopProxy<AppEvent>>,
+    // Polled from `about_to_wait`, the web build fetches the files in a task of its own
+    #[cfg(all(debug_assertions, not(target_arch = "wasm32")))]
+    shader_watcher: (crate::reload::ShaderWatcher, web_time::Instant),
+    // Latest source of every shader module reloaded so far
+    reloaded: Vec<(String, String)>,
+    // Viewers whose pipelines failed to rebuild, their errors are on screen until none is left
+    shader_errors: Rc<RefCell<HashSet<WindowId>>>,
+}
+impl App {
+    pub fn new(event_proxy: EventLoopProxy<AppEvent>) -> Self {
+        let event_proxy = Rc::new(event_proxy);
+        #[cfg(all(debug_assertions, target_arch = "wasm32"))]
+        platform::watch_shaders(event_proxy.clone());
+        Self {
+            viewers: Vec::new(),
+            pending: Vec::new(),
+            resumed: false,
+            event_proxy,
+            #[cfg(all(debug_assertions, not(target_arch = "wasm32")))]
+            shader_watcher: (crate::reload::ShaderWatcher::new(), web_time::Instant::now()),
+            reloaded: Vec::new(),
+            shader_errors: Rc::default(),
+        }
+    }
+
+    /// Open a viewer once the event loop runs
+    #[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+    pub fn add_viewer(&mut self, request: ViewerRequest) {
+        self.pending.push(request);
+    }
+
+    /// Rebuild the pipelines of every viewer with the changed shader modules. Viewers
+    /// without pipelines yet get them once their surface is configured
+    fn reload_shaders(&mut self, modules: Vec<(String, String)>) {
+        let names: Vec<&str> = modules.iter().map(|(name, _)| name.as_str()).collect();
+        log::warn!("Reloading {}", names.join(", "));
+        for (name, source) in modules {
+            match self.reloaded.iter_mut().find(|(module, _)| *module == name) {
+                Some((_, previous)) => *previous = source,
+                None => self.reloaded.push((name, source)),
+            }
+        }
+        // Every module reloaded so far, a module still broken keeps failing the rebuild
+        for slot in self.viewers.iter_mut() {
+            if slot.surface_configured {
+                rebuild_shaders(slot, self.reloaded.clone(), &self.shader_errors);
+            } else {
+                slot.stale_shaders = true;
+            }
+        }
+    }
+
+    fn slot(&mut self, window_id: WindowId) -> Option<&mut Slot> {
+        self.viewers.iter_mut().find(|slot| slot.window_id == window_id)
+    }
+
+    fn open(&mut self, event_loop: &ActiveEventLoop, mut request: ViewerRequest) {
+        let window_attributes = platform::window_attributes(&mut request)
+            .with_title("gpudemo");
+        let window = event_loop.create_window(window_attributes).unwrap();
+
+        let size = platform::prepare_window(&window);
+        self.viewers.push(Slot {
+            window_id: window.id(),
+            state: request.state.clone(),
+            surface_configured: false,
+            stale_shaders: !self.reloaded.is_empty(),
+        });
+
+        let state_clone = request.state;
+        let event_proxy_clone = self.event_proxy.clone();
+        let scene_source = request.scene_source;
+        let ready = request.ready;
+        let render_scale = request.render_scale;
+        platform::spawn(async move {
+            let mut new_state = Viewer::new(window).await;
+            new_state.set_render_scale(render_scale);
+            load_scene(&mut new_state, scene_source).await;
+
+            match state_clone.try_borrow_mut() {
+                Ok(mut state_obj) => {
+                    *state_obj = Some(new_state);
+                    let window_clone = state_obj.as_ref().map(|state| state.window.clone()).unwrap();
+
+                    if let Err(e) = event_proxy_clone.send_event(AppEvent::InitStateDone {
+                        window: window_clone,
+                        size,
+                    }) {
+                        log::warn!("Failed to send user event: {}", e);
+                    }
+                    if let Some(ready) = ready {
+                        let _ = ready.send(());
+                    }
+                }
+                Err(_) => log::warn!("Could not borrow for initialisation"),
+            };
+        });
+    }
+}
+
+/// Build the pipelines of the viewer with the shader `modules` and swap them in. On errors
+/// the viewer keeps its pipelines and the errors go over the canvas, until every viewer
+/// rebuilt without errors
+fn rebuild_shaders(
+    slot: &Slot,
+    modules: Vec<(String, String)>,
+    shader_errors: &Rc<RefCell<HashSet<WindowId>>>,
+) {
+    let Some(rebuild) = slot
+        .state
+        .try_borrow()
+        .ok()
+        .and_then(|state| Some(state.as_ref()?.rebuild_shaders(modules)))
+    else {
+        return;
+    };
+    let state = slot.state.clone();
+    let window_id = slot.window_id;
+    let shader_errors = shader_errors.clone();
+    platform::spawn(async move {
+        match rebuild.await {
+            Ok(update) => {
+                let Ok(mut state) = state.try_borrow_mut() else {
+                    return;
+                };
+                let Some(state) = state.as_mut() else {
+                    return;
+                };
+                state.install_shaders(update);
+                state.window().request_redraw();
+                let mut shader_errors = shader_errors.borrow_mut();
+                if shader_errors.remove(&window_id) && shader_errors.is_empty() {
+                    platform::clear_error();
+                }
+            }
+            Err(errors) => {
+                log::error!("Shaders:\n{errors}");
+                platform::report_error(&errors);
+                shader_errors.borrow_mut().insert(window_id);
+            }
+        }
+    });
+}
+
+/// Load the scene of `source` into the viewer, falls back to the demo scene
+async fn load_scene(viewer: &mut Viewer, source: Option<String>) {
+    let Some(source) = source else {
+        viewer.make_world();
+        return;
+    };
+    let loaded = match platform::fetch_scene(&source).await {
+        Ok(scene) => viewer.load_scene(&scene),
+        Err(e) => Err(e),
+    };
+    if let Err(e) = loaded {
+        let message = format!("{source}: {e}");
+        log::error!("{message}");
+        platform::report_error(&message);
+        viewer.make_world();
+    }
+}
+
+impl ApplicationHandler<AppEvent> for App {
+    fn user_event(&mut self, event_loop: &ActiveEventLoop, event: AppEvent) {
+        match event {
+            AppEvent::InitStateDone{window, size} => {
+                log::warn!("State initialisation is done");
+                let Some(slot) = self.slot(window.id()) else {
+                    return;
+                };
+                // Ask for resize
+                slot.surface_configured = false;
+                // Applied right away on native, in which case no Resized event may follow.
+                // A canvas of the page may have been resized before the viewer existed
+                let size = match size {
+                    Some(size) => window.as_ref().request_inner_size(size),
+                    None => Some(window.inner_size()),
+                };
+                if let Some(size) = size {
+                    if let Ok(mut state) = slot.state.try_borrow_mut() {
+                        if let Some(state) = state.as_mut() {
+                            slot.surface_configured = state.on_resize(size);
+                        }
+                    }
+                }
+            }
+            AppEvent::AddViewer(request) => {
+                if self.resumed {
+                    self.open(event_loop, request);
+                } else {
+                    self.pending.push(request);
+                }
+            }
+            AppEvent::ShadersChanged(modules) => self.reload_shaders(modules),
+        }
+    }
+
+    fn resumed(&mut self, event_loop: &ActiveEventLoop) {
+        self.resumed = true;
+        for request in std::mem::take(&mut self.pending) {
+            self.open(event_loop, request);
+        }
+    }
+
+    fn window_event(
+        &mut self,
+        event_loop: &ActiveEventLoop,
+        window_id: WindowId,
+        event: WindowEvent,
+    ) {
+        let Some(slot) = self.slot(window_id) else {
+            return;
+        };
+        match event {
+            WindowEvent::CloseRequested
+            | WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        state: ElementState::Pressed,
+                        physical_key:
+                            winit::keyboard::PhysicalKey::Code(winit::keyboard::KeyCode::Escape),
+                        ..
+                    },
+                ..
+            } => {
+                event_loop.exit();
+            }
+
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        state: ElementState::Pressed,
+                        physical_key:
+                            winit::keyboard::PhysicalKey::Code(winit::keyboard::KeyCode::KeyP),
+                        repeat: false,
+                        ..
+                    },
+                ..
+            } => {
+                let Some(screenshot) = slot
+                    .state
+                    .try_borrow()
+                    .ok()
+                    .and_then(|state| Some(state.as_ref()?.screenshot_png()))
+                else {
+                    return;
+                };
+                platform::spawn(async move {
+                    match screenshot.await {
+                        Ok(png) => platform::save_png(SCREENSHOT_NAME, &png),
+                        Err(e) => log::error!("Screenshot: {e}"),
+                    }
+                });
+            }
+
+            WindowEvent::Resized(physical_size) => {
+                log::warn!("Event: resize");
+                if let Ok(mut state) = slot.state.try_borrow_mut() {
+                    if let Some(state) = state.as_mut() {
+                        slot.surface_configured = state.on_resize(physical_size);
+                    }
+                }
+            }
+
+            WindowEvent::RedrawRequested => {
+                if let Ok(mut state) = slot.state.try_borrow_mut() {
+                    if let Some(state) = state.as_mut() {
+                        if slot.surface_configured {
+                            match state.render() {
+                                Ok(_) => {}
+                                Err(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) => {
+                                    // NOTE: Hmm
+                                    log::warn!("Lost");
+                                    slot.surface_configured = state.on_resize(state.size);
+                                }
+
+                                Err(wgpu::SurfaceError::OutOfMemory) => {
+                                    log::error!("Out of memory");
+                                    event_loop.exit();
+                                }
+
+                                Err(wgpu::SurfaceError::Timeout) => {
+                                    log::warn!("Surface timeout");
+                                }
+                                Err(wgpu::SurfaceError::Other) => {
+                                    log::warn!("Unknown error");
+                                }
+                            };
+                        }
+                    }
+                }
+            }
+            WindowEvent::Occluded(occluded) => {
+                if let Ok(mut state) = slot.state.try_borrow_mut() {
+                    if let Some(state) = state.as_mut() {
+                        state.visible = !occluded;
+                    }
+                }
+            }
+
+            WindowEvent::MouseInput { .. }
+            | WindowEvent::CursorMoved { .. }
+            | WindowEvent::CursorLeft { .. }
+            | WindowEvent::MouseWheel { .. } => {
+                if let Ok(mut state) = slot.state.try_borrow_mut() {
+                    if let Some(state) = state.as_mut() {
+                        state.input(&event);
+                    }
+                }
+            }
+            _ => {}
+        };
+
+    }
+
+    // Only viewers with something new to draw get a frame, the loop sleeps otherwise
+    fn about_to_wait(&mut self, _event_loop: &ActiveEventLoop) {
+        #[cfg(all(debug_assertions, not(target_arch = "wasm32")))]
+        {
+            let (watcher, next_poll) = &mut self.shader_watcher;
+            if web_time::Instant::now() >= *next_poll {
+                *next_poll = web_time::Instant::now() + crate::reload::POLL_INTERVAL;
+                let modules = watcher.poll();
+                if !modules.is_empty() {
+                    self.reload_shaders(modules);
+                }
+            }
+            _event_loop.set_control_flow(winit::event_loop::ControlFlow::WaitUntil(
+                self.shader_watcher.1,
+            ));
+        }
+        for slot in self.viewers.iter_mut() {
+            if slot.stale_shaders && slot.surface_configured {
+                slot.stale_shaders = false;
+                rebuild_shaders(slot, self.reloaded.clone(), &self.shader_errors);
+            }
+        }
+        for slot in &self.viewers {
+            if let Ok(state) = slot.state.try_borrow() {
+                if let Some(state) = state.as_ref() {
+                    if state.wants_frame() {
+                        state.window().request_redraw();
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+mod platform {
+    use crate::scene::{SceneDesc, SceneError};
+    use winit::platform::web::{WindowAttributesExtWebSys, WindowExtWebSys};
+    use winit::window::{Window, WindowAttributes};
+
+    use super::ViewerRequest;
+    #[cfg(debug_assertions)]
+    use {super::AppEvent, std::rc::Rc, winit::event_loop::EventLoopProxy};
+
+    /// Draw into the canvas of the request, if any
+    pub fn window_attributes(request: &mut ViewerRequest) -> WindowAttributes {
+        Window::default_attributes().with_canvas(request.canvas.take())
+    }
+
+    /// Append a new canvas to the page and return the size it should fill.
+    /// A canvas of the page keeps its CSS size, winit follows it with a ResizeObserver.
+    /// Either way the surface gets the device pixels of the canvas, CSS size times
+    /// `devicePixelRatio`
+    pub fn prepare_window(window: &Window) -> Option<winit::dpi::Size> {
+        let web_window = web_sys::window().expect("No web window");
+        let canvas = window.canvas()?;
+        if canvas.is_connected() {
+            return None;
+        }
+        web_window
+            .document()
+            .and_then(|doc| doc.body())
+            .and_then(|body| {
+                body.append_child(&web_sys::Element::from(canvas)).ok()?;
+                Some(())
+            })
+            .expect("Couldn't append canvas to document body.");
+        let web_width = web_window.inner_width().unwrap().as_f64().unwrap();
+        let web_height = web_window.inner_height().unwrap().as_f64().unwrap();
+        // In CSS pixels
+        Some(winit::dpi::LogicalSize::new(web_width, web_height).into())
+    }
+
+    pub fn spawn(future: impl std::future::Future<Output = ()> + 'static) {
+        wasm_bindgen_futures::spawn_local(future);
+    }
+
+    /// Show `message` over the canvas, replacing the previous one
+    pub fn report_error(message: &str) {
+        let Some(document) = web_sys::window().and_then(|w| w.document()) else {
+            return;
+        };
+        let Some(body) = document.body() else {
+            return;
+        };
+        let panel = match document.get_element_by_id("gpudemo-errors") {
+            Some(panel) => panel,
+            None => {
+                let Ok(panel) = document.create_element("pre") else {
+                    return;
+                };
+                panel.set_id("gpudemo-errors");
+                let _ = panel.set_attribute(
+                    "style",
+                    "position: fixed; top: 0; left: 0; margin: 1em; padding: 1em; \
+                     background: rgba(0, 0, 0, 0.8); color: #f66; font-size: 14px; \
+                     white-space: pre-wrap;",
+                );
+                let _ = body.append_child(&panel);
+                panel
+            }
+        };
+        panel.set_text_content(Some(message));
+    }
+
+    /// Remove the message of `report_error`
+    pub fn clear_error() {
+        if let Some(panel) = web_sys::window()
+            .and_then(|w| w.document())
+            .and_then(|document| document.get_element_by_id("gpudemo-errors"))
+        {
+            panel.remove();
+        }
+    }
+
+    /// Fetch the shader files over and over, the app rebuilds the pipelines on changes
+    #[cfg(debug_assertions)]
+    pub fn watch_shaders(event_proxy: Rc<EventLoopProxy<AppEvent>>) {
+        spawn(async move {
+            let mut watcher = crate::reload::ShaderWatcher::new();
+            loop {
+                let modules = watcher.poll().await;
+                let changed = !modules.is_empty();
+                if changed && event_proxy.send_event(AppEvent::ShadersChanged(modules)).is_err() {
+                    // The event loop is gone
+                    return;
+                }
+                crate::web::sleep(crate::reload::POLL_INTERVAL.as_millis() as i32).await;
+            }
+        });
+    }
+
+    pub async fn fetch_scene(url: &str) -> Result<SceneDesc, SceneError> {
+        let json = crate::web::fetch_text(url)
+            .await
+            .map_err(|e| SceneError::Fetch(format!("{e:?}")))?;
+        SceneDesc::from_json(&json)
+    }
+
+    /// Let the browser download `bytes`
+    pub fn save_png(name: &str, bytes: &[u8]) {
+        if let Err(e) = crate::web::download_png(name, bytes) {
+            log::error!("{name}: {e:?}");
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+mod platform {
+    use crate::scene::{SceneDesc, SceneError};
+    use winit::window::{Window, WindowAttributes};
+
+    use super::ViewerRequest;
+
+    pub fn window_attributes(_request: &mut ViewerRequest) -> WindowAttributes {
+        Window::default_attributes()
+    }
+
+    pub fn prepare_window(window: &Window) -> Option<winit::dpi::Size> {
+        Some(window.inner_size().into())
+    }
+
+    // Nothing else runs on the event loop thread before the viewer exists
+    pub fn spawn(future: impl std::future::Future<Output = ()> + 'static) {
+        pollster::block_on(future);
+    }
+
+    // Already logged
+    pub fn report_error(_message: &str) {}
+
+    pub fn clear_error() {}
+
+    pub async fn fetch_scene(path: &str) -> Result<SceneDesc, SceneError> {
+        SceneDesc::load(path)
+    }
+
+    pub fn save_png(name: &str, bytes: &[u8]) {
+        match std::fs::write(name, bytes) {
+            Ok(()) => log::info!("Saved {name}"),
+            Err(e) => log::error!("{name}: {e}"),
+        }
+    }
+}
@@ -0,0 +1,340 @@
+//! Render a scene file to a PNG or an OpenEXR file without any window, or every frame of its
+//! animation to numbered files
+//!
+//! Exit codes: 1 bad arguments or image size, 2 no usable GPU adapter, 3 invalid scene,
+//! 4 could not write the image
+
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use gpudemo::cpu::CpuRenderer;
+use gpudemo::headless::{self, ExrOptions, ExrPrecision, HeadlessError, HeadlessOptions};
+use gpudemo::scene::SceneDesc;
+use gpudemo::settings::RenderSettings;
+use gpudemo::{Image, RenderLayers, Renderer};
+
+const EXIT_USAGE: u8 = 1;
+const EXIT_ADAPTER: u8 = 2;
+const EXIT_SCENE: u8 = 3;
+const EXIT_IO: u8 = 4;
+
+const USAGE: &str = "\
+usage: gpudemo-render <scene.json> <output.png|output.exr> [options]
+       gpudemo-render <scene.json> <frames/out_####.png> --sequence [options]
+  --width <px>         image width, default 640
+  --height <px>        image height, default 360
+  --spp <n>            samples per pixel, default from the scene or 16
+  --max-bounces <n>    bounces per path, default from the scene or 100
+  --seed <n>           random seed, default from the scene or 3
+  --deterministic      integer random numbers, same image for the same scene on the same adapter
+  --software           only use a software adapter
+  --cpu                use the CPU reference path tracer, no adapter needed
+  --sequence           render every frame of the scene animation, the #s of the output
+                       are replaced by the zero padded frame number
+  --float              .exr only, 32 bit float beauty and albedo instead of half
+  --aovs <list>        .exr only, comma separated layers to add to the beauty:
+                       albedo, normal, depth, id or all";
+
+struct Args {
+    scene: PathBuf,
+    output: PathBuf,
+    width: u32,
+    height: u32,
+    // Settings given on the command line, they win over the ones of the scene
+    spp: Option<u32>,
+    max_bounces: Option<u32>,
+    seed: Option<u32>,
+    deterministic: bool,
+    software: bool,
+    cpu: bool,
+    sequence: bool,
+    // Set when the output is an .exr file
+    exr: Option<ExrOptions>,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Args, String> {
+    let mut positional = Vec::new();
+    let mut parsed = Args {
+        scene: PathBuf::new(),
+        output: PathBuf::new(),
+        width: 640,
+        height: 360,
+        spp: None,
+        max_bounces: None,
+        seed: None,
+        deterministic: false,
+        software: false,
+        cpu: false,
+        sequence: false,
+        exr: None,
+    };
+    let mut precision = None;
+    let mut aovs = None;
+
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| -> Result<u32, String> {
+            let value = args.next().ok_or(format!("{name} needs a value"))?;
+            value
+                .parse()
+                .map_err(|_| format!("{name}: '{value}' is not a positive integer"))
+        };
+        match arg.as_str() {
+            "--width" => parsed.width = value("--width")?,
+            "--height" => parsed.height = value("--height")?,
+            "--spp" => parsed.spp = Some(value("--spp")?),
+            "--max-bounces" => parsed.max_bounces = Some(value("--max-bounces")?),
+            "--seed" => parsed.seed = Some(value("--seed")?),
+            "--deterministic" => parsed.deterministic = true,
+            "--software" => parsed.software = true,
+            "--cpu" => parsed.cpu = true,
+            "--sequence" => parsed.sequence = true,
+            "--float" => precision = Some(ExrPrecision::Float),
+            "--aovs" => aovs = Some(args.next().ok_or("--aovs needs a value")?),
+            "-h" | "--help" => return Err(String::new()),
+            _ if arg.starts_with("--") => return Err(format!("unknown option {arg}")),
+            _ => positional.push(PathBuf::from(arg)),
+        }
+    }
+
+    let [scene, output] = <[PathBuf; 2]>::try_from(positional)
+        .map_err(|_| "expected a scene file and an output path".to_string())?;
+    if parsed.width == 0 || parsed.height == 0 || parsed.spp == Some(0) {
+        return Err("width, height and spp must be greater than 0".to_string());
+    }
+    if parsed.sequence && !output.to_string_lossy().contains('#') {
+        return Err("--sequence needs # in the output path, e.g. out_####.png".to_string());
+    }
+    let is_exr = output
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("exr"));
+    if is_exr {
+        let mut options = ExrOptions {
+            precision: precision.unwrap_or_default(),
+            ..Default::default()
+        };
+        for aov in aovs.iter().flat_map(|aovs| aovs.split(',')) {
+            match aov.trim() {
+                "albedo" => options.albedo = true,
+                "normal" => options.normal = true,
+                "depth" => options.depth = true,
+                "id" => options.object_id = true,
+                "all" => {
+                    options.albedo = true;
+                    options.normal = true;
+                    options.depth = true;
+                    options.object_id = true;
+                }
+                other => return Err(format!("--aovs: unknown layer '{other}'")),
+            }
+        }
+        parsed.exr = Some(options);
+    } else if precision.is_some() || aovs.is_some() {
+        return Err("--float and --aovs need an .exr output".to_string());
+    }
+    parsed.scene = scene;
+    parsed.output = output;
+    Ok(parsed)
+}
+
+// `pattern` with its run of #s replaced by `frame`, padded to as many digits
+fn frame_path(pattern: &std::path::Path, frame: u32) -> PathBuf {
+    let pattern = pattern.to_string_lossy();
+    let start = pattern.find('#').expect("Checked by parse_args");
+    let width = pattern[start..].chars().take_while(|c| *c == '#').count();
+    let number = format!("{frame:0width$}");
+    PathBuf::from(format!("{}{number}{}", &pattern[..start], &pattern[start + width..]))
+}
+
+impl Args {
+    fn apply(&self, settings: &mut RenderSettings) {
+        settings.spp = self.spp.unwrap_or(settings.spp);
+        settings.max_bounces = self.max_bounces.unwrap_or(settings.max_bounces);
+        settings.seed = self.seed.unwrap_or(settings.seed);
+        settings.deterministic |= self.deterministic;
+    }
+}
+
+// What a render reads back, depending on the output format
+enum Frame {
+    Image(Image),
+    Layers(RenderLayers),
+}
+
+impl Frame {
+    // The scene goes along, see `Image::to_png` and `headless::write_exr`
+    fn write(
+        &self,
+        path: &PathBuf,
+        scene: &SceneDesc,
+        exr: Option<&ExrOptions>,
+    ) -> Result<(), String> {
+        match (self, exr) {
+            (Frame::Layers(layers), Some(options)) => {
+                let file = std::fs::File::create(path).map_err(|e| e.to_string())?;
+                headless::write_exr(layers, options, Some(scene), std::io::BufWriter::new(file))
+                    .map_err(|e| e.to_string())
+            }
+            (Frame::Image(image), _) => {
+                let png = image.to_png(Some(scene)).map_err(|e| e.to_string())?;
+                std::fs::write(path, png).map_err(|e| e.to_string())
+            }
+            (Frame::Layers(_), None) => unreachable!("Layers are only read back for EXR"),
+        }
+    }
+}
+
+// There is only ever one
+#[allow(clippy::large_enum_variant)]
+enum Backend {
+    Gpu(Renderer),
+    Cpu(CpuRenderer),
+}
+
+impl Backend {
+    fn new(args: &Args, scene: &SceneDesc) -> Result<Self, HeadlessError> {
+        // The scene was validated by main
+        if args.cpu {
+            let mut renderer = CpuRenderer::new();
+            renderer.load_scene(scene).unwrap();
+            if !renderer.resize(args.width, args.height) {
+                return Err(HeadlessError::InvalidSize {
+                    width: args.width,
+                    height: args.height,
+                });
+            }
+            return Ok(Backend::Cpu(renderer));
+        }
+        let options = HeadlessOptions {
+            force_fallback_adapter: args.software,
+            ..Default::default()
+        };
+        let mut renderer = pollster::block_on(headless::create_renderer(&options))?;
+        renderer.load_scene(scene).unwrap();
+        if !renderer.resize(args.width, args.height) {
+            return Err(HeadlessError::InvalidSize {
+                width: args.width,
+                height: args.height,
+            });
+        }
+        Ok(Backend::Gpu(renderer))
+    }
+
+    // Accumulate `settings.spp` samples with `settings`, which may pose the camera.
+    // `layers` reads back the linear frame and the AOVs instead of the RGBA8 one
+    fn render(
+        &mut self,
+        settings: RenderSettings,
+        layers: bool,
+        progress: impl FnMut(u32, u32),
+    ) -> Result<Frame, HeadlessError> {
+        match self {
+            Backend::Gpu(renderer) => {
+                renderer.set_settings(settings);
+                Ok(match layers {
+                    true => {
+                        Frame::Layers(pollster::block_on(headless::render_layers(renderer, progress))?)
+                    }
+                    false => {
+                        Frame::Image(pollster::block_on(headless::render_samples(renderer, progress))?)
+                    }
+                })
+            }
+            Backend::Cpu(renderer) => {
+                renderer.set_settings(settings);
+                let image = renderer.render_samples(progress);
+                Ok(match layers {
+                    true => Frame::Layers(renderer.layers()),
+                    false => Frame::Image(image),
+                })
+            }
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    let args = match parse_args(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            if !e.is_empty() {
+                eprintln!("error: {e}");
+            }
+            eprintln!("{USAGE}");
+            return ExitCode::from(EXIT_USAGE);
+        }
+    };
+
+    let scene = SceneDesc::load(&args.scene).and_then(|mut scene| {
+        args.apply(&mut scene.settings);
+        scene.validate()?;
+        scene.to_world()?;
+        Ok(scene)
+    });
+    let scene = match scene {
+        Ok(scene) => scene,
+        Err(e) => {
+            eprintln!("error: {}: {e}", args.scene.display());
+            return ExitCode::from(EXIT_SCENE);
+        }
+    };
+
+    let mut backend = match Backend::new(&args, &scene) {
+        Ok(backend) => backend,
+        Err(e @ HeadlessError::InvalidSize { .. }) => {
+            eprintln!("error: {e}");
+            return ExitCode::from(EXIT_USAGE);
+        }
+        Err(e) => {
+            eprintln!("error: {e}");
+            return ExitCode::from(EXIT_ADAPTER);
+        }
+    };
+
+    // A single image renders the scene at its own `settings.time`
+    let frames = match args.sequence {
+        true => 0..scene.animation.frame_count(),
+        false => 0..1,
+    };
+    let frame_count = frames.len();
+    for frame in frames {
+        let (settings, output) = match args.sequence {
+            true => (
+                scene.animation.frame_settings(&scene.settings, frame),
+                frame_path(&args.output, frame),
+            ),
+            false => (scene.settings, args.output.clone()),
+        };
+        let progress = |done, total| {
+            match args.sequence {
+                true => eprint!("\rframe {}/{frame_count} sample {done}/{total}", frame + 1),
+                false => eprint!("\rsample {done}/{total}"),
+            }
+            let _ = std::io::stderr().flush();
+        };
+        let rendered = match backend.render(settings, args.exr.is_some(), progress) {
+            Ok(rendered) => rendered,
+            Err(e) => {
+                eprintln!();
+                eprintln!("error: {e}");
+                return ExitCode::from(EXIT_ADAPTER);
+            }
+        };
+
+        // The file carries the settings of its frame
+        let scene = SceneDesc {
+            settings,
+            ..scene.clone()
+        };
+        if let Err(e) = rendered.write(&output, &scene, args.exr.as_ref()) {
+            eprintln!();
+            eprintln!("error: {}: {e}", output.display());
+            return ExitCode::from(EXIT_IO);
+        }
+    }
+    eprintln!();
+    match args.sequence {
+        true => eprintln!("wrote {frame_count} frames to {}", args.output.display()),
+        false => eprintln!("wrote {}", args.output.display()),
+    }
+    ExitCode::SUCCESS
+}
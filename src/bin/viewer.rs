@@ -0,0 +1,11 @@
+//! Desktop viewer, the same app as the web build without the browser round trip
+
+#[cfg(not(target_arch = "wasm32"))]
+fn main() -> Result<(), winit::error::EventLoopError> {
+    // gpudemo-viewer [scene.json]
+    gpudemo::native::run(std::env::args_os().nth(1).map(Into::into))
+}
+
+// The web build starts from `gpudemo::web::start` instead
+#[cfg(target_arch = "wasm32")]
+fn main() {}
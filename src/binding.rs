@@ -1,263 +1,153 @@
-pub fn uniform_bind_group_lay(device: &wgpu::Device, binding: u32) -> wgpu::BindGroupLayout {
-    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-        label: None,
-        entries: &[wgpu::BindGroupLayoutEntry {
-            binding,
-            visibility: wgpu::ShaderStages::COMPUTE,
-            ty: wgpu::BindingType::Buffer {
-                ty: wgpu::BufferBindingType::Uniform,
-                has_dynamic_offset: false,
-                min_binding_size: None,
-            },
-            count: None,
-        }],
-    })
-}
+use std::hash::BuildHasher;
 
-pub fn buf_bind_group_lay(
-    device: &wgpu::Device,
-    binding: u32,
-    read_only: bool,
-) -> wgpu::BindGroupLayout {
-    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-        label: None,
-        entries: &[wgpu::BindGroupLayoutEntry {
-            binding,
-            visibility: wgpu::ShaderStages::COMPUTE,
-            ty: wgpu::BindingType::Buffer {
-                ty: wgpu::BufferBindingType::Storage { read_only },
-                has_dynamic_offset: false,
-                min_binding_size: None,
-            },
-            count: None,
-        }],
-    })
+pub fn uniform_entry(binding: u32) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::COMPUTE,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    }
 }
 
-pub fn mira(device: &wgpu::Device, hit_record_bind: u32, rays_bind: u32) -> wgpu::BindGroupLayout {
-    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-        label: Some("Shade stage hits and rays"),
-        entries: &[
-            // HitRecord buffer
-            wgpu::BindGroupLayoutEntry {
-                binding: hit_record_bind,
-                visibility: wgpu::ShaderStages::COMPUTE,
-                ty: wgpu::BindingType::Buffer {
-                    ty: wgpu::BufferBindingType::Storage { read_only: false },
-                    has_dynamic_offset: false,
-                    min_binding_size: None,
-                },
-                count: None,
-            },
-            // Rays buffer
-            wgpu::BindGroupLayoutEntry {
-                binding: rays_bind,
-                visibility: wgpu::ShaderStages::COMPUTE,
-                ty: wgpu::BindingType::Buffer {
-                    ty: wgpu::BufferBindingType::Storage { read_only: false },
-                    has_dynamic_offset: false,
-                    min_binding_size: None,
-                },
-                count: None,
-            },
-        ],
-    })
+pub fn buf_entry(binding: u32, read_only: bool) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::COMPUTE,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Storage { read_only },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    }
 }
 
-pub fn mira_bind<'a, 'b>(
-    device: &wgpu::Device,
-    rec_rs: wgpu::BindingResource<'a>,
-    rays_rs: wgpu::BindingResource<'b>,
-    hit_record_bind: u32,
-    rays_bind: u32,
-    layout: &wgpu::BindGroupLayout,
-) -> wgpu::BindGroup {
-    device.create_bind_group(&wgpu::BindGroupDescriptor {
-        label: None,
-        // Get it from our compute pipeline
-        layout,
-        entries: &[
-            wgpu::BindGroupEntry {
-                binding: hit_record_bind,
-                resource: rec_rs,
-            },
-            wgpu::BindGroupEntry {
-                binding: rays_bind,
-                resource: rays_rs,
-            },
-        ],
-    })
+pub fn img_texture_entry(binding: u32, format: wgpu::TextureFormat) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::COMPUTE,
+        ty: wgpu::BindingType::StorageTexture {
+            access: wgpu::StorageTextureAccess::WriteOnly,
+            format,
+            view_dimension: wgpu::TextureViewDimension::D2,
+        },
+        count: None,
+    }
 }
 
-pub fn sphere_n_dim_group_lay(
-    device: &wgpu::Device,
-    sphere_bind: u32,
-    dim_bind: u32,
-    read_only: bool,
-) -> wgpu::BindGroupLayout {
-    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-        label: Some("Shade stage spheres"),
-        // Sphere buffer
-        entries: &[
-            wgpu::BindGroupLayoutEntry {
-                binding: sphere_bind,
-                visibility: wgpu::ShaderStages::COMPUTE,
-                ty: wgpu::BindingType::Buffer {
-                    ty: wgpu::BufferBindingType::Storage { read_only },
-                    has_dynamic_offset: false,
-                    min_binding_size: None,
-                },
-                count: None,
-            },
-            // Dim uniform
-            wgpu::BindGroupLayoutEntry {
-                binding: dim_bind,
-                visibility: wgpu::ShaderStages::COMPUTE,
-                ty: wgpu::BindingType::Buffer {
-                    ty: wgpu::BufferBindingType::Uniform,
-                    has_dynamic_offset: false,
-                    min_binding_size: None,
-                },
-                count: None,
-            },
-        ],
-    })
+pub fn sampled_texture_entry(binding: u32) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::COMPUTE,
+        ty: wgpu::BindingType::Texture {
+            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            view_dimension: wgpu::TextureViewDimension::D2,
+            multisampled: false,
+        },
+        count: None,
+    }
 }
 
-pub fn sphere_n_dim_bind_group<'a, 'b>(
-    device: &wgpu::Device,
-    sphere_rs: wgpu::BindingResource<'a>,
-    dim_rs: wgpu::BindingResource<'b>,
-    sphere_bind: u32,
-    dim_bind: u32,
-    layout: &wgpu::BindGroupLayout,
-) -> wgpu::BindGroup {
-    device.create_bind_group(&wgpu::BindGroupDescriptor {
-        label: None,
-        // Get it from our compute pipeline
-        layout,
-        entries: &[
-            wgpu::BindGroupEntry {
-                binding: sphere_bind,
-                resource: sphere_rs,
-            },
-            wgpu::BindGroupEntry {
-                binding: dim_bind,
-                resource: dim_rs,
-            },
-        ],
-    })
+pub fn sampler_entry(binding: u32) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::COMPUTE,
+        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+        count: None,
+    }
 }
 
-pub fn material_n_seed_group_lay(
+pub fn group_lay(
     device: &wgpu::Device,
-    material_bind: u32,
-    seed_bind: u32,
-    read_only: bool,
+    label: Option<&str>,
+    entries: &[wgpu::BindGroupLayoutEntry],
 ) -> wgpu::BindGroupLayout {
-    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-        label: None,
-        // Materials buffer
-        entries: &[
-            wgpu::BindGroupLayoutEntry {
-                binding: material_bind,
-                visibility: wgpu::ShaderStages::COMPUTE,
-                ty: wgpu::BindingType::Buffer {
-                    ty: wgpu::BufferBindingType::Storage { read_only },
-                    has_dynamic_offset: false,
-                    min_binding_size: None,
-                },
-                count: None,
-            },
-            // Seed uniform
-            wgpu::BindGroupLayoutEntry {
-                binding: seed_bind,
-                visibility: wgpu::ShaderStages::COMPUTE,
-                ty: wgpu::BindingType::Buffer {
-                    ty: wgpu::BufferBindingType::Uniform,
-                    has_dynamic_offset: false,
-                    min_binding_size: None,
-                },
-                count: None,
-            },
-        ],
-    })
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor { label, entries })
 }
 
-pub fn material_n_seed_bind_group<'a, 'b>(
-    device: &wgpu::Device,
-    materials_rs: wgpu::BindingResource<'a>,
-    seed_rs: wgpu::BindingResource<'b>,
-    material_bind: u32,
-    seed_bind: u32,
-    layout: &wgpu::BindGroupLayout,
-) -> wgpu::BindGroup {
-    device.create_bind_group(&wgpu::BindGroupDescriptor {
-        label: None,
-        // Get it from our compute pipeline
-        layout,
-        entries: &[
-            wgpu::BindGroupEntry {
-                binding: material_bind,
-                resource: materials_rs,
-            },
-            wgpu::BindGroupEntry {
-                binding: seed_bind,
-                resource: seed_rs,
-            },
-        ],
-    })
+/// A resource that can be bound, used to key cached bind groups
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum BindKey {
+    Buffer(wgpu::Buffer),
+    // First bytes of a buffer
+    BufferRange(wgpu::Buffer, wgpu::BufferSize),
+    TextureView(wgpu::TextureView),
+    Sampler(wgpu::Sampler),
 }
 
-pub fn bind_group_from<'a>(
-    device: &wgpu::Device,
-    resource: wgpu::BindingResource<'a>,
-    binding: u32,
-    layout: &wgpu::BindGroupLayout,
-) -> wgpu::BindGroup {
-    device.create_bind_group(&wgpu::BindGroupDescriptor {
-        label: None,
-        // Get it from our compute pipeline
-        layout,
-        entries: &[wgpu::BindGroupEntry { binding, resource }],
-    })
+impl BindKey {
+    fn resource(&self) -> wgpu::BindingResource<'_> {
+        match self {
+            BindKey::Buffer(buf) => buf.as_entire_binding(),
+            BindKey::BufferRange(buf, size) => wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                buffer: buf,
+                offset: 0,
+                size: Some(*size),
+            }),
+            BindKey::TextureView(view) => wgpu::BindingResource::TextureView(view),
+            BindKey::Sampler(sampler) => wgpu::BindingResource::Sampler(sampler),
+        }
+    }
 }
 
-pub fn img_texture_bind_group_lay(
-    device: &wgpu::Device,
-    format: wgpu::TextureFormat,
-    binding: u32,
-) -> wgpu::BindGroupLayout {
-    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-        label: None,
-        entries: &[
-            wgpu::BindGroupLayoutEntry {
-                binding,
-                visibility: wgpu::ShaderStages::COMPUTE,
-                ty: wgpu::BindingType::StorageTexture {
-                    access: wgpu::StorageTextureAccess::WriteOnly,
-                    format,
-                    view_dimension: wgpu::TextureViewDimension::D2,
-                },
-                count: None,
-            },
-        ],
-    })
+/// Bind groups keyed on their layout and the (binding, resource) pairs they hold.
+/// Looked up by the hash of the borrowed key, only a miss copies the key.
+/// NOTE: Entries keep their resources alive, clear the cache whenever those get replaced
+#[derive(Default)]
+pub struct BindGroupCache {
+    hasher: std::collections::hash_map::RandomState,
+    // Groups by key hash, the rare ones sharing a hash are told apart by their key
+    groups: std::collections::HashMap<u64, Vec<CachedGroup>>,
 }
 
-pub fn img_texture_bind_group<'a>(
-    device: &wgpu::Device,
-    texture_view: &'a wgpu::TextureView,
-    binding: u32,
-    layout: &wgpu::BindGroupLayout,
-) -> wgpu::BindGroup {
-    device.create_bind_group(&wgpu::BindGroupDescriptor {
-        label: Some("Main bind group"),
-        // Get it from our compute pipeline
-        layout,
-        entries: &[wgpu::BindGroupEntry {
-            binding,
-            resource: wgpu::BindingResource::TextureView(texture_view),
-        }],
-    })
+struct CachedGroup {
+    layout: wgpu::BindGroupLayout,
+    entries: Vec<(u32, BindKey)>,
+    group: wgpu::BindGroup,
+}
+
+impl BindGroupCache {
+    pub fn get(
+        &mut self,
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        entries: &[(u32, BindKey)],
+    ) -> wgpu::BindGroup {
+        let hash = self.hasher.hash_one((layout, entries));
+        let hit = self.groups.get(&hash).and_then(|cached| {
+            cached
+                .iter()
+                .find(|c| c.layout == *layout && c.entries == entries)
+        });
+        if let Some(hit) = hit {
+            return hit.group.clone();
+        }
+
+        let bind_entries: Vec<wgpu::BindGroupEntry> = entries
+            .iter()
+            .map(|(binding, key)| wgpu::BindGroupEntry {
+                binding: *binding,
+                resource: key.resource(),
+            })
+            .collect();
+        let group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout,
+            entries: &bind_entries,
+        });
+        self.groups.entry(hash).or_default().push(CachedGroup {
+            layout: layout.clone(),
+            entries: entries.to_vec(),
+            group: group.clone(),
+        });
+        group
+    }
+
+    pub fn clear(&mut self) {
+        self.groups.clear();
+    }
 }
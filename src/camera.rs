@@ -1,5 +1,7 @@
 use nalgebra::Vector3;
 
+use crate::scene::CameraDesc;
+
 type Vector3f = Vector3<f32>;
 
 #[repr(C, packed)]
@@ -53,18 +55,29 @@ pub struct Camera {
 #[repr(C, packed)]
 #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct CameraLean {
-    pixeloo: [f32; 3],
+    pub(crate) pixeloo: [f32; 3],
     _pad0: u32,
-    pixel_delta_u: [f32; 3],
+    pub(crate) pixel_delta_u: [f32; 3],
     _pad1: u32,
-    pixel_delta_v: [f32; 3],
+    pub(crate) pixel_delta_v: [f32; 3],
     _pad2: u32,
-    pos: [f32; 3],
+    pub(crate) pos: [f32; 3],
     _pad3: u32,
 }
 
-const _: () = assert!(std::mem::size_of::<Camera>() % 16 == 0);
-const _: () = assert!(std::mem::size_of::<CameraLean>() % 16 == 0);
+const _: () = assert!(std::mem::size_of::<Camera>().is_multiple_of(16));
+const _: () = assert!(std::mem::size_of::<CameraLean>().is_multiple_of(16));
+
+crate::wgsl_struct!(CameraLean as "Camera" {
+    pixeloo: "vec3<f32>",
+    _pad0: "u32",
+    pixel_delta_u: "vec3<f32>",
+    _pad1: "u32",
+    pixel_delta_v: "vec3<f32>",
+    _pad2: "u32",
+    pos: "vec3<f32>",
+    _pad3: "u32",
+});
 // const _: () = assert!(std::mem::align_of::<Camera>() == 16);
 
 impl Default for Camera {
@@ -181,6 +194,15 @@ impl Camera {
         }
     }
 
+    /// Place the camera of a scene for a picture of `width` x `height` pixels
+    pub fn set_view(&mut self, view: &CameraDesc, width: u32, height: u32) {
+        self.set_focal_length(view.focal_length);
+        self.set_resolution(width, height, true);
+        self.position = view.position;
+        self.look_at = view.look_at;
+        self.up_vector = view.up;
+    }
+
     pub fn set_focal_length(&mut self, focal_length: f32) {
         self.focal_length = focal_length;
         self.update_camera_config();
@@ -0,0 +1,120 @@
+use nalgebra::{Rotation3, Unit, Vector3};
+use winit::event::{ElementState, MouseButton, MouseScrollDelta, WindowEvent};
+
+use crate::scene::CameraDesc;
+
+type Vector3f = Vector3<f32>;
+
+// Keeps the camera from flipping over the poles, in radians from the up vector
+const MIN_POLAR: f32 = 0.05;
+// Distance factor of one wheel notch
+const ZOOM_STEP: f32 = 1.1;
+
+/// Orbit around `look_at` with the left mouse button, dolly with the wheel
+#[derive(Default)]
+pub struct OrbitControls {
+    dragging: bool,
+    cursor: Option<(f64, f64)>,
+}
+
+impl OrbitControls {
+    /// The camera after `event`, None if the event doesn't move it.
+    /// `height` is the window height, a drag over it turns the camera by half a turn
+    pub fn handle(&mut self, event: &WindowEvent, view: &CameraDesc, height: u32) -> Option<CameraDesc> {
+        match event {
+            WindowEvent::MouseInput {
+                state,
+                button: MouseButton::Left,
+                ..
+            } => {
+                self.dragging = *state == ElementState::Pressed;
+                None
+            }
+            WindowEvent::CursorLeft { .. } => {
+                self.cursor = None;
+                None
+            }
+            WindowEvent::CursorMoved { position, .. } => {
+                let previous = self.cursor.replace((position.x, position.y));
+                let (x, y) = previous.filter(|_| self.dragging)?;
+                let per_pixel = std::f32::consts::PI / height.max(1) as f32;
+                let dx = (position.x - x) as f32 * per_pixel;
+                let dy = (position.y - y) as f32 * per_pixel;
+                (dx != 0.0 || dy != 0.0).then(|| orbit(view, -dx, -dy))
+            }
+            WindowEvent::MouseWheel { delta, .. } => {
+                let notches = match delta {
+                    MouseScrollDelta::LineDelta(_, y) => *y,
+                    MouseScrollDelta::PixelDelta(position) => position.y as f32 / 100.0,
+                };
+                (notches != 0.0).then(|| dolly(view, ZOOM_STEP.powf(-notches)))
+            }
+            _ => None,
+        }
+    }
+}
+
+// Turn the camera position around `look_at` by `yaw` about the up vector, then by `pitch`
+// away from it
+fn orbit(view: &CameraDesc, yaw: f32, pitch: f32) -> CameraDesc {
+    let target = Vector3f::from(view.look_at);
+    let offset = Vector3f::from(view.position) - target;
+    let Some(up) = Unit::try_new(Vector3f::from(view.up), f32::EPSILON) else {
+        return *view;
+    };
+    if offset.norm() < f32::EPSILON {
+        return *view;
+    }
+
+    let offset = Rotation3::from_axis_angle(&up, yaw) * offset;
+    let polar = offset.angle(&up);
+    let pitch = pitch.clamp(MIN_POLAR - polar, std::f32::consts::PI - MIN_POLAR - polar);
+    let offset = match Unit::try_new(up.cross(&offset), f32::EPSILON) {
+        Some(right) => Rotation3::from_axis_angle(&right, pitch) * offset,
+        None => offset,
+    };
+
+    CameraDesc {
+        position: (target + offset).into(),
+        ..*view
+    }
+}
+
+// Scale the distance to `look_at`
+fn dolly(view: &CameraDesc, factor: f32) -> CameraDesc {
+    let target = Vector3f::from(view.look_at);
+    let offset = (Vector3f::from(view.position) - target) * factor;
+    if offset.norm() < 1e-3 {
+        return *view;
+    }
+    CameraDesc {
+        position: (target + offset).into(),
+        ..*view
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn orbit_keeps_distance_and_stops_at_the_poles() {
+        let view = CameraDesc {
+            position: [0.0, 0.0, -10.0],
+            look_at: [0.0; 3],
+            up: [0.0, 1.0, 0.0],
+            focal_length: 35.0,
+        };
+        let turned = orbit(&view, 1.0, 0.0);
+        assert!((Vector3f::from(turned.position).norm() - 10.0).abs() < 1e-4);
+        assert!(turned.position[1].abs() < 1e-4);
+
+        // Far past the top, stops right before it
+        let top = orbit(&view, 0.0, -10.0);
+        let polar = Vector3f::from(top.position).angle(&Vector3f::y());
+        assert!((polar - MIN_POLAR).abs() < 1e-4, "{polar}");
+
+        let closer = dolly(&view, 0.5);
+        assert_eq!(closer.position, [0.0, 0.0, -5.0]);
+    }
+}
@@ -0,0 +1,450 @@
+use nalgebra::Vector3;
+use rayon::prelude::*;
+
+use crate::animation::Timeline;
+use crate::camera::{Camera, CameraLean};
+use crate::renderer::{Image, PrimaryHit, RenderLayers};
+use crate::scene::{CameraDesc, EnvironmentDesc, SceneDesc, SceneError};
+use crate::settings::{DebugView, RenderSettings};
+use crate::sphere::{Material, Sphere};
+use crate::world::Scene;
+
+type Vector3f = Vector3<f32>;
+
+/// Reference path tracer on the CPU, one thread per row.
+/// Follows rays.wgsl, intersect.wgsl and shade.wgsl step by step, including the hash based
+/// random numbers, so its images match the GPU ones up to float precision
+pub struct CpuRenderer {
+    scene: Scene,
+    camera: Camera,
+    view: CameraDesc,
+    timeline: Timeline,
+    environment: EnvironmentDesc,
+    settings: RenderSettings,
+    // Sum of the samples of each pixel
+    accum: Vec<[f32; 4]>,
+    // Samples accumulated since the last reset
+    sample: u32,
+    width: u32,
+    height: u32,
+}
+
+struct Hit {
+    point: Vector3f,
+    normal: Vector3f,
+    material_id: i32,
+    // Index into `Scene::spheres`
+    slot: usize,
+}
+
+// Random numbers of common/random.wgsl
+struct Rng {
+    seed: f32,
+    pixel: [f32; 2],
+    // PCG state of the deterministic mode
+    state: Option<u32>,
+}
+
+fn pcg(v: u32) -> u32 {
+    let state = v.wrapping_mul(747796405).wrapping_add(2891336453);
+    let word = ((state >> ((state >> 28) + 4)) ^ state).wrapping_mul(277803737);
+    (word >> 22) ^ word
+}
+
+impl Rng {
+    fn new(settings: &RenderSettings, pixel_index: u32, x: u32, y: u32, sample: u32) -> Self {
+        Self {
+            seed: settings.frame_seed() as f32 + sample as f32 * 0.618,
+            pixel: [x as f32, y as f32],
+            state: settings
+                .deterministic
+                .then(|| pcg(pixel_index ^ pcg(sample ^ pcg(settings.frame_seed())))),
+        }
+    }
+
+    fn hash(&self, seed: f32) -> f32 {
+        let dot = self.pixel[0] * 12.9898 + self.pixel[1] * 78.233;
+        let x = (seed / 100.0 * dot).sin() * 43758.547;
+        // WGSL fract, not f32::fract which keeps the sign
+        x - x.floor()
+    }
+
+    fn rand3(&mut self) -> Vector3f {
+        if let Some(state) = self.state.as_mut() {
+            let mut next = || {
+                *state = pcg(*state);
+                (*state >> 8) as f32 / 16777216.0
+            };
+            return Vector3f::new(next(), next(), next());
+        }
+        let v = Vector3f::new(
+            self.hash(self.seed),
+            self.hash(self.seed + 1.0),
+            self.hash(self.seed + 2.0),
+        );
+        self.seed += 3.0;
+        v
+    }
+
+    fn in_sphere(&mut self) -> Vector3f {
+        loop {
+            let v = self.rand3() * 2.0 - Vector3f::repeat(1.0);
+            let len_sq = v.dot(&v);
+            if len_sq > 0.0001 {
+                return v / len_sq.sqrt();
+            }
+        }
+    }
+
+    fn in_hemisphere(&mut self, normal: &Vector3f) -> Vector3f {
+        let dir = self.in_sphere();
+        if normal.dot(&dir) > 0.0 {
+            dir
+        } else {
+            -dir
+        }
+    }
+}
+
+fn hit_sphere(center: Vector3f, radius: f32, ro: &Vector3f, rv: &Vector3f, tmin: f32, tmax: f32) -> f32 {
+    let oc = center - ro;
+    let a = rv.dot(rv);
+    let h = rv.dot(&oc);
+    let c = oc.dot(&oc) - radius * radius;
+    let discriminant = h * h - a * c;
+
+    if discriminant < 0.0 {
+        return -1.0;
+    }
+    let sqroot = discriminant.sqrt();
+
+    let mut root = (h - sqroot) / a;
+    if root <= tmin || root >= tmax {
+        root = (h + sqroot) / a;
+        if root <= tmin || root >= tmax {
+            return -1.0;
+        }
+    }
+    root
+}
+
+// Origin and direction of the camera ray of a pixel, rays.wgsl
+fn primary_ray(sensor: &CameraLean, x: u32, y: u32) -> (Vector3f, Vector3f) {
+    let pos = Vector3f::from(sensor.pos);
+    let pixel_pos = Vector3f::from(sensor.pixeloo)
+        + Vector3f::from(sensor.pixel_delta_u) * x as f32
+        + Vector3f::from(sensor.pixel_delta_v) * y as f32;
+    (pos, pixel_pos - pos)
+}
+
+fn to_srgb(linear: f32) -> f32 {
+    if linear <= 0.0031308 {
+        linear * 12.92
+    } else {
+        1.055 * linear.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+// Same rounding as a store to an rgba8unorm texture
+fn to_unorm8(v: f32) -> u8 {
+    (v.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+impl Default for CpuRenderer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CpuRenderer {
+    pub fn new() -> Self {
+        Self {
+            scene: Scene::new(),
+            camera: Camera::new(),
+            view: CameraDesc::default(),
+            timeline: Timeline::default(),
+            environment: EnvironmentDesc::default(),
+            settings: RenderSettings::default(),
+            accum: Vec::new(),
+            sample: 0,
+            width: 0,
+            height: 0,
+        }
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    pub fn settings(&self) -> &RenderSettings {
+        &self.settings
+    }
+
+    /// Also poses the camera when `time` changes, like `Renderer::set_settings`
+    pub fn set_settings(&mut self, settings: RenderSettings) {
+        let moved = settings.time != self.settings.time;
+        self.settings = settings;
+        self.reset_accumulation();
+        if let Some(view) = self.timeline.pose(settings.time).filter(|_| moved) {
+            self.set_view(view);
+        }
+    }
+
+    pub fn sample_count(&self) -> u32 {
+        self.sample
+    }
+
+    pub fn reset_accumulation(&mut self) {
+        self.sample = 0;
+    }
+
+    pub fn set_world(&mut self, materials: &[Material], spheres: &[Sphere]) -> Result<(), SceneError> {
+        self.scene = Scene::from_world(materials, spheres)?;
+        self.reset_accumulation();
+        Ok(())
+    }
+
+    pub fn scene(&self) -> &Scene {
+        &self.scene
+    }
+
+    /// Edit the scene in place, like `Renderer::scene_mut`
+    pub fn scene_mut(&mut self) -> &mut Scene {
+        &mut self.scene
+    }
+
+    /// Validate a scene and take its world, camera, environment and settings
+    pub fn load_scene(&mut self, scene: &SceneDesc) -> Result<(), SceneError> {
+        scene.validate()?;
+        let (materials, spheres) = scene.to_world()?;
+        self.set_world(&materials, &spheres)?;
+        self.environment = scene.environment;
+        self.timeline = Timeline {
+            animation: scene.animation.clone(),
+            camera: scene.camera,
+        };
+        self.set_settings(scene.settings);
+        self.set_view(self.timeline.pose(scene.settings.time).unwrap_or(scene.camera));
+        Ok(())
+    }
+
+    pub fn set_view(&mut self, view: CameraDesc) {
+        self.view = view;
+        if self.width > 0 && self.height > 0 {
+            self.camera.set_view(&self.view, self.width, self.height);
+        }
+        self.reset_accumulation();
+    }
+
+    pub fn resize(&mut self, width: u32, height: u32) -> bool {
+        if width == 0 || height == 0 {
+            return false;
+        }
+        self.width = width;
+        self.height = height;
+        self.camera.set_view(&self.view, width, height);
+        self.accum = vec![[0.0; 4]; (width * height) as usize];
+        self.reset_accumulation();
+        true
+    }
+
+    /// Accumulate one more sample of every pixel
+    pub fn render_sample(&mut self) {
+        if self.scene.is_dirty() {
+            self.scene.take_dirty();
+            self.reset_accumulation();
+        }
+        if self.settings.deterministic && self.sample >= self.settings.spp {
+            return;
+        }
+        let sensor = self.camera.compute_sensor();
+        let width = self.width as usize;
+        let sample = self.sample;
+        let mut accum = std::mem::take(&mut self.accum);
+        let scene = &*self;
+
+        accum
+            .par_chunks_mut(width)
+            .enumerate()
+            .for_each(|(y, row)| {
+                for (x, sum) in row.iter_mut().enumerate() {
+                    let color = scene.shade(&sensor, x as u32, y as u32, sample);
+                    if sample == 0 {
+                        *sum = [0.0; 4];
+                    }
+                    for (s, c) in sum.iter_mut().zip(color) {
+                        *s += c;
+                    }
+                }
+            });
+
+        self.accum = accum;
+        self.sample += 1;
+    }
+
+    /// Average of the samples so far, in the sRGB RGBA8 layout of the GPU frame texture
+    pub fn frame(&self) -> Image {
+        let n = self.sample.max(1) as f32;
+        let data = self
+            .accum
+            .iter()
+            .flat_map(|sum| {
+                [
+                    to_unorm8(to_srgb(sum[0] / n)),
+                    to_unorm8(to_srgb(sum[1] / n)),
+                    to_unorm8(to_srgb(sum[2] / n)),
+                    to_unorm8(sum[3] / n),
+                ]
+            })
+            .collect();
+        Image {
+            width: self.width,
+            height: self.height,
+            data,
+        }
+    }
+
+    /// The linear average of the samples so far and the primary hits, like
+    /// `Renderer::request_layers`
+    pub fn layers(&self) -> RenderLayers {
+        let sensor = self.camera.compute_sensor();
+        let hits = (0..self.height).flat_map(|y| (0..self.width).map(move |x| (x, y)));
+        let hits = hits.map(|(x, y)| {
+            let (pos, dir) = primary_ray(&sensor, x, y);
+            self.hit_any(&pos, &dir, self.settings.tmin).map(|hit| PrimaryHit {
+                point: hit.point.into(),
+                normal: hit.normal.into(),
+                material_id: hit.material_id,
+                slot: hit.slot,
+            })
+        });
+        RenderLayers::from_hits(
+            self.width,
+            self.height,
+            self.sample,
+            &self.accum,
+            hits,
+            &self.view,
+            &self.scene,
+        )
+    }
+
+    /// Accumulate the `spp` samples of the settings, see `headless::render_samples`
+    pub fn render_samples(&mut self, mut progress: impl FnMut(u32, u32)) -> Image {
+        let spp = self.settings.spp.max(1);
+        self.reset_accumulation();
+        for sample in 0..spp {
+            self.render_sample();
+            progress(sample + 1, spp);
+        }
+        self.frame()
+    }
+
+    fn material(&self, material_id: i32) -> Material {
+        self.scene
+            .materials()
+            .get(material_id as usize)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    fn albedo(&self, material_id: i32) -> Vector3f {
+        let albedo = self.material(material_id).albedo;
+        Vector3f::new(albedo[0], albedo[1], albedo[2])
+    }
+
+    // Closest sphere between `tmin` and `settings.tmax`, `hit_any` in shade.wgsl
+    fn hit_any(&self, o: &Vector3f, dir: &Vector3f, tmin: f32) -> Option<Hit> {
+        let mut closest_hit = -1.0;
+        let mut closest_sphere = 0;
+        let tmax = self.settings.tmax;
+        for (i, sphere) in self.scene.spheres().iter().enumerate() {
+            let s = hit_sphere(Vector3f::from(sphere.position), sphere.radius, o, dir, tmin, tmax);
+            if s > 0.0 && (closest_hit < 0.0 || s < closest_hit) {
+                closest_hit = s;
+                closest_sphere = i;
+            }
+        }
+        if closest_hit < 0.0 {
+            return None;
+        }
+
+        let sphere = self.scene.spheres()[closest_sphere];
+        let point = o + dir * closest_hit;
+        let mut normal = (point - Vector3f::from(sphere.position)).normalize();
+        // Make it point outward, `set_hit_orientation`
+        if normal.dot(dir) > 0.0 {
+            normal = -normal;
+        }
+        Some(Hit {
+            point,
+            normal,
+            material_id: sphere.material_id,
+            slot: closest_sphere,
+        })
+    }
+
+    fn shade(&self, sensor: &CameraLean, x: u32, y: u32, sample: u32) -> [f32; 4] {
+        let (pos, mut ray_dir) = primary_ray(sensor, x, y);
+
+        // intersect.wgsl
+        let Some(mut hit) = self.hit_any(&pos, &ray_dir, self.settings.tmin) else {
+            return [0.0, 0.0, 0.0, 1.0];
+        };
+        let primary = (hit.normal, self.albedo(hit.material_id));
+
+        // shade.wgsl
+        let mut rng = Rng::new(&self.settings, y * self.width + x, x, y, sample);
+        let mut attenuation = self.albedo(hit.material_id);
+        let mut bounces = 0;
+        while bounces < self.settings.max_bounces {
+            let material = self.material(hit.material_id);
+            let dir = match material.kind {
+                0 => {
+                    let dir = (rng.in_hemisphere(&hit.normal) + hit.normal).normalize();
+                    if dir.dot(&dir) < 0.0001 * 0.0001 {
+                        hit.normal
+                    } else {
+                        dir
+                    }
+                }
+                1 => {
+                    let reflected = ray_dir - 2.0 * ray_dir.dot(&hit.normal) * hit.normal;
+                    let dir = (reflected.normalize() + material.fuzz * rng.in_hemisphere(&hit.normal))
+                        .normalize();
+                    if dir.dot(&hit.normal) <= 0.0 {
+                        break;
+                    }
+                    dir
+                }
+                _ => break,
+            };
+
+            bounces += 1;
+            match self.hit_any(&hit.point, &dir, self.settings.bounce_tmin) {
+                Some(next) => {
+                    attenuation.component_mul_assign(&self.albedo(next.material_id));
+                    ray_dir = dir;
+                    hit = next;
+                }
+                None => {
+                    attenuation.component_mul_assign(&Vector3f::from(self.environment.sky));
+                    break;
+                }
+            }
+        }
+        if self.settings.clamp > 0.0 {
+            attenuation = attenuation.map(|c| c.min(self.settings.clamp));
+        }
+
+        let color = match self.settings.debug {
+            DebugView::Off => attenuation,
+            DebugView::Normal => primary.0 * 0.5 + Vector3f::repeat(0.5),
+            DebugView::Albedo => primary.1,
+            DebugView::Bounces => {
+                Vector3f::repeat(bounces as f32 / self.settings.max_bounces.max(1) as f32)
+            }
+        };
+        [color.x, color.y, color.z, 1.0]
+    }
+}
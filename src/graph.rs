@@ -0,0 +1,960 @@
+use std::collections::HashMap;
+
+use crate::binding::{self, BindGroupCache, BindKey};
+use crate::shader::{Composed, Composer, ShaderError};
+
+/// How a pass uses one of its bound resources
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Access {
+    Uniform,
+    Read,
+    ReadWrite,
+    // Storage texture, write only
+    Write,
+    // Filtered texture reads, with a `Sampler`
+    Sampled,
+    Sampler,
+}
+
+/// A resource bound by a pass: `@binding(binding)` gets the resource called `resource`
+#[derive(Copy, Clone, Debug)]
+pub struct Slot {
+    pub binding: u32,
+    pub resource: &'static str,
+    pub access: Access,
+}
+
+impl Slot {
+    pub const fn new(binding: u32, resource: &'static str, access: Access) -> Self {
+        Self {
+            binding,
+            resource,
+            access,
+        }
+    }
+}
+
+/// A compute pass dispatched once per pixel.
+/// The shader declares `override WORKGROUP_X: u32` and `override WORKGROUP_Y: u32` for its
+/// `@workgroup_size`, the graph sets them from `workgroup_size`
+pub struct PassDesc {
+    pub label: &'static str,
+    // Module composed by the shader `Composer`
+    pub shader: &'static str,
+    pub defines: Vec<&'static str>,
+    pub entry_point: &'static str,
+    pub workgroup_size: (u32, u32),
+    // Values of the other `override` declarations of the shader, by name
+    pub constants: Vec<(&'static str, f64)>,
+    // Bind groups, in @group order
+    pub groups: Vec<Vec<Slot>>,
+}
+
+/// A render pass drawing one triangle over the whole of `target`, cleared to black first.
+/// The vertex shader makes the triangle from the vertex index, there are no vertex buffers
+pub struct RenderPassDesc {
+    pub label: &'static str,
+    // Module composed by the shader `Composer`
+    pub shader: &'static str,
+    pub defines: Vec<&'static str>,
+    pub vertex_entry: &'static str,
+    pub fragment_entry: &'static str,
+    // Color attachment, see `add_target`
+    pub target: &'static str,
+    // Bind groups, in @group order
+    pub groups: Vec<Vec<Slot>>,
+}
+
+#[derive(Debug)]
+pub enum GraphError {
+    UnknownResource {
+        pass: &'static str,
+        resource: &'static str,
+    },
+    // Resource was declared but nothing was imported for it yet
+    MissingResource(&'static str),
+    BadAccess {
+        pass: &'static str,
+        resource: &'static str,
+        access: Access,
+    },
+    // Render pass target that isn't declared with `add_target`
+    BadTarget {
+        pass: &'static str,
+        resource: &'static str,
+    },
+    UnknownPass(&'static str),
+    Shader {
+        pass: &'static str,
+        error: ShaderError,
+    },
+    // Over the limits of the device
+    WorkgroupSize {
+        pass: &'static str,
+        size: (u32, u32),
+    },
+}
+
+impl std::fmt::Display for GraphError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GraphError::UnknownResource { pass, resource } => {
+                write!(f, "pass '{pass}' binds undeclared resource '{resource}'")
+            }
+            GraphError::MissingResource(resource) => {
+                write!(f, "resource '{resource}' was never imported")
+            }
+            GraphError::BadAccess {
+                pass,
+                resource,
+                access,
+            } => write!(f, "pass '{pass}' cannot use '{resource}' as {access:?}"),
+            GraphError::BadTarget { pass, resource } => {
+                write!(f, "pass '{pass}' cannot draw into '{resource}'")
+            }
+            GraphError::UnknownPass(pass) => write!(f, "no pass named '{pass}'"),
+            GraphError::Shader { pass, error } => write!(f, "pass '{pass}': {error}"),
+            GraphError::WorkgroupSize { pass, size: (x, y) } => {
+                write!(f, "pass '{pass}': workgroup size {x}x{y} is over the device limits")
+            }
+        }
+    }
+}
+
+impl std::error::Error for GraphError {}
+
+enum ResourceKind {
+    // Reallocated on resize
+    Buffer {
+        bytes_per_pixel: u64,
+        usage: wgpu::BufferUsages,
+    },
+    // Always frame sized
+    Texture {
+        format: wgpu::TextureFormat,
+        usage: wgpu::TextureUsages,
+    },
+    // Owned by the caller, e.g. uniforms and scene buffers
+    Imported,
+    // Color attachment of render passes, owned by the caller and never bound
+    Target(wgpu::TextureFormat),
+}
+
+#[derive(PartialEq)]
+enum Allocation {
+    // Bound in full when the size is None
+    Buffer(wgpu::Buffer, Option<wgpu::BufferSize>),
+    Texture(wgpu::Texture, wgpu::TextureView),
+    // Imported texture
+    View(wgpu::TextureView),
+    Sampler(wgpu::Sampler),
+}
+
+struct Resource {
+    kind: ResourceKind,
+    allocation: Option<Allocation>,
+}
+
+/// Everything a pipeline of a pass is specialized on
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct PipelineKey {
+    defines: Vec<&'static str>,
+    workgroup_size: (u32, u32),
+    // Values as bits, f64 is not `Eq`
+    constants: Vec<(&'static str, u64)>,
+}
+
+impl PipelineKey {
+    fn of(desc: &PassDesc) -> Self {
+        let mut constants: Vec<_> = desc
+            .constants
+            .iter()
+            .map(|(name, value)| (*name, value.to_bits()))
+            .collect();
+        constants.sort_unstable();
+        Self {
+            defines: desc.defines.clone(),
+            workgroup_size: desc.workgroup_size,
+            constants,
+        }
+    }
+
+    // Override constants of the pipeline, the workgroup size included
+    fn constants(&self) -> HashMap<String, f64> {
+        let (x, y) = self.workgroup_size;
+        self.constants
+            .iter()
+            .map(|(name, bits)| (name.to_string(), f64::from_bits(*bits)))
+            .chain([("WORKGROUP_X".into(), x as f64), ("WORKGROUP_Y".into(), y as f64)])
+            .collect()
+    }
+}
+
+// Composed modules of a pass by defines
+type Modules = HashMap<Vec<&'static str>, wgpu::ShaderModule>;
+type Pipelines = HashMap<PipelineKey, wgpu::ComputePipeline>;
+
+struct ComputePass {
+    desc: PassDesc,
+    layouts: Vec<wgpu::BindGroupLayout>,
+    pipeline_layout: Option<wgpu::PipelineLayout>,
+    modules: Modules,
+    // Every specialization used so far, going back to one doesn't rebuild it
+    pipelines: Pipelines,
+    // Bind groups of the current resources, built by the first `record` after they change
+    bound: Vec<wgpu::BindGroup>,
+}
+
+impl ComputePass {
+    fn new(desc: PassDesc) -> Self {
+        Self {
+            desc,
+            layouts: Vec::new(),
+            pipeline_layout: None,
+            modules: HashMap::new(),
+            pipelines: HashMap::new(),
+            bound: Vec::new(),
+        }
+    }
+
+    fn pipeline(&self) -> Option<&wgpu::ComputePipeline> {
+        self.pipelines.get(&PipelineKey::of(&self.desc))
+    }
+
+    fn create_module(
+        &self,
+        device: &wgpu::Device,
+        composer: &Composer,
+        defines: &[&'static str],
+    ) -> Result<(wgpu::ShaderModule, Composed), GraphError> {
+        create_module(device, composer, self.desc.label, self.desc.shader, defines)
+    }
+
+    fn create_pipeline(
+        &self,
+        device: &wgpu::Device,
+        module: &wgpu::ShaderModule,
+        key: &PipelineKey,
+    ) -> wgpu::ComputePipeline {
+        log::debug!("Specializing '{}': {:?}", self.desc.label, key);
+        device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some(self.desc.label),
+            layout: self.pipeline_layout.as_ref(),
+            module,
+            entry_point: Some(self.desc.entry_point),
+            compilation_options: wgpu::PipelineCompilationOptions {
+                constants: &key.constants(),
+                ..Default::default()
+            },
+            cache: None,
+        })
+    }
+
+    fn record(
+        &mut self,
+        device: &wgpu::Device,
+        resources: &HashMap<&'static str, Resource>,
+        cache: &mut BindGroupCache,
+        encoder: &mut wgpu::CommandEncoder,
+        (width, height): (u32, u32),
+    ) -> Result<(), GraphError> {
+        if self.bound.len() != self.layouts.len() {
+            self.bound = bind_groups(device, resources, cache, &self.layouts, &self.desc.groups)?;
+        }
+        let pipeline = self
+            .pipeline()
+            .expect("Render graph must be compiled before execution");
+
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some(self.desc.label),
+            ..Default::default()
+        });
+        compute_pass.set_pipeline(pipeline);
+        for (i, grp) in self.bound.iter().enumerate() {
+            compute_pass.set_bind_group(i as u32, grp, &[]);
+        }
+        let (wx, wy) = self.desc.workgroup_size;
+        compute_pass.dispatch_workgroups(width.div_ceil(wx), height.div_ceil(wy), 1);
+        Ok(())
+    }
+}
+
+struct RenderPass {
+    desc: RenderPassDesc,
+    layouts: Vec<wgpu::BindGroupLayout>,
+    pipeline_layout: Option<wgpu::PipelineLayout>,
+    pipeline: Option<wgpu::RenderPipeline>,
+    // See `ComputePass::bound`
+    bound: Vec<wgpu::BindGroup>,
+}
+
+impl RenderPass {
+    fn compile(
+        &mut self,
+        device: &wgpu::Device,
+        composer: &Composer,
+        resources: &HashMap<&'static str, Resource>,
+    ) -> Result<(), GraphError> {
+        let format = self.format(resources)?;
+        let layouts = group_layouts(
+            device,
+            resources,
+            self.desc.label,
+            &self.desc.groups,
+            wgpu::ShaderStages::VERTEX_FRAGMENT,
+        )?;
+        let layout_refs: Vec<&wgpu::BindGroupLayout> = layouts.iter().collect();
+        self.pipeline_layout = Some(device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some(self.desc.label),
+            bind_group_layouts: &layout_refs,
+            push_constant_ranges: &[],
+        }));
+        self.layouts = layouts;
+        let (module, _) = self.create_module(device, composer)?;
+        self.pipeline = Some(self.create_pipeline(device, &module, format));
+        Ok(())
+    }
+
+    // Of the target, declared with `add_target`
+    fn format(
+        &self,
+        resources: &HashMap<&'static str, Resource>,
+    ) -> Result<wgpu::TextureFormat, GraphError> {
+        match resources.get(self.desc.target) {
+            Some(Resource {
+                kind: ResourceKind::Target(format),
+                ..
+            }) => Ok(*format),
+            _ => Err(GraphError::BadTarget {
+                pass: self.desc.label,
+                resource: self.desc.target,
+            }),
+        }
+    }
+
+    fn create_module(
+        &self,
+        device: &wgpu::Device,
+        composer: &Composer,
+    ) -> Result<(wgpu::ShaderModule, Composed), GraphError> {
+        create_module(
+            device,
+            composer,
+            self.desc.label,
+            self.desc.shader,
+            &self.desc.defines,
+        )
+    }
+
+    fn create_pipeline(
+        &self,
+        device: &wgpu::Device,
+        module: &wgpu::ShaderModule,
+        format: wgpu::TextureFormat,
+    ) -> wgpu::RenderPipeline {
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some(self.desc.label),
+            layout: self.pipeline_layout.as_ref(),
+            vertex: wgpu::VertexState {
+                module,
+                entry_point: Some(self.desc.vertex_entry),
+                compilation_options: Default::default(),
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module,
+                entry_point: Some(self.desc.fragment_entry),
+                compilation_options: Default::default(),
+                targets: &[Some(format.into())],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        })
+    }
+
+    fn record(
+        &mut self,
+        device: &wgpu::Device,
+        resources: &HashMap<&'static str, Resource>,
+        cache: &mut BindGroupCache,
+        encoder: &mut wgpu::CommandEncoder,
+    ) -> Result<(), GraphError> {
+        if self.bound.len() != self.layouts.len() {
+            self.bound = bind_groups(device, resources, cache, &self.layouts, &self.desc.groups)?;
+        }
+        let pipeline = self
+            .pipeline
+            .as_ref()
+            .expect("Render graph must be compiled before execution");
+        let target = match resources
+            .get(self.desc.target)
+            .and_then(|res| res.allocation.as_ref())
+        {
+            Some(Allocation::View(view)) => view,
+            _ => return Err(GraphError::MissingResource(self.desc.target)),
+        };
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some(self.desc.label),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        render_pass.set_pipeline(pipeline);
+        for (i, grp) in self.bound.iter().enumerate() {
+            render_pass.set_bind_group(i as u32, grp, &[]);
+        }
+        render_pass.draw(0..3, 0..1);
+        Ok(())
+    }
+}
+
+enum Node {
+    Compute(ComputePass),
+    Render(RenderPass),
+}
+
+enum Rebuilt {
+    Compute(Modules, Pipelines),
+    // None if the pass wasn't compiled yet
+    Render(Option<wgpu::RenderPipeline>),
+}
+
+/// Modules and pipelines of a graph built again from other shader sources, see
+/// `RenderGraph::rebuild`
+pub struct Rebuild {
+    // Per pass, in order
+    passes: Vec<Rebuilt>,
+    // Every new module with the source it was created from
+    pub modules: Vec<(wgpu::ShaderModule, Composed)>,
+}
+
+/// Compute and render passes and the resources they bind.
+/// Passes run in insertion order, wgpu tracks the usage of every bound resource and inserts
+/// the barriers between passes, so the graph only has to keep the order and the bindings.
+#[derive(Default)]
+pub struct RenderGraph {
+    resources: HashMap<&'static str, Resource>,
+    passes: Vec<Node>,
+    bind_groups: BindGroupCache,
+    size: (u32, u32),
+}
+
+impl RenderGraph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Declare a buffer of `bytes_per_pixel` per pixel of the frame, see `resize`
+    pub fn add_buffer(
+        &mut self,
+        name: &'static str,
+        bytes_per_pixel: u64,
+        usage: wgpu::BufferUsages,
+    ) {
+        self.declare(
+            name,
+            ResourceKind::Buffer {
+                bytes_per_pixel,
+                usage,
+            },
+        );
+    }
+
+    pub fn add_texture(
+        &mut self,
+        name: &'static str,
+        format: wgpu::TextureFormat,
+        usage: wgpu::TextureUsages,
+    ) {
+        self.declare(name, ResourceKind::Texture { format, usage });
+    }
+
+    /// Declare a resource owned outside of the graph, see `import_buffer`
+    pub fn add_import(&mut self, name: &'static str) {
+        self.declare(name, ResourceKind::Imported);
+    }
+
+    /// Declare a color attachment of render passes, see `import_target`
+    pub fn add_target(&mut self, name: &'static str, format: wgpu::TextureFormat) {
+        self.declare(name, ResourceKind::Target(format));
+    }
+
+    /// Bind a buffer owned outside of the graph, replacing the previous one
+    pub fn import_buffer(&mut self, name: &'static str, buffer: wgpu::Buffer) {
+        self.import(name, Allocation::Buffer(buffer, None));
+    }
+
+    /// Bind the first `size` bytes of a buffer owned outside of the graph, so `arrayLength`
+    /// in the shaders only sees the used part of an over-allocated buffer
+    pub fn import_buffer_range(
+        &mut self,
+        name: &'static str,
+        buffer: wgpu::Buffer,
+        size: wgpu::BufferSize,
+    ) {
+        self.import(name, Allocation::Buffer(buffer, Some(size)));
+    }
+
+    /// Bind a texture owned outside of the graph, see `import_buffer`
+    pub fn import_view(&mut self, name: &'static str, view: wgpu::TextureView) {
+        self.import(name, Allocation::View(view));
+    }
+
+    /// Bind a sampler owned outside of the graph, see `import_buffer`
+    pub fn import_sampler(&mut self, name: &'static str, sampler: wgpu::Sampler) {
+        self.import(name, Allocation::Sampler(sampler));
+    }
+
+    /// Draw the render passes targeting `name` into `view`, e.g. the surface texture of the
+    /// frame. Targets aren't bound, the bind groups stay
+    pub fn import_target(&mut self, name: &'static str, view: wgpu::TextureView) {
+        if let Some(resource) = self.resources.get_mut(name) {
+            resource.allocation = Some(Allocation::View(view));
+        }
+    }
+
+    fn import(&mut self, name: &'static str, allocation: Allocation) {
+        let resource = self.resources.entry(name).or_insert(Resource {
+            kind: ResourceKind::Imported,
+            allocation: None,
+        });
+        if resource.allocation.as_ref() == Some(&allocation) {
+            return;
+        }
+        resource.allocation = Some(allocation);
+        self.unbind();
+    }
+
+    pub fn add_pass(&mut self, desc: PassDesc) {
+        self.passes.push(Node::Compute(ComputePass::new(desc)));
+    }
+
+    pub fn add_render_pass(&mut self, desc: RenderPassDesc) {
+        self.passes.push(Node::Render(RenderPass {
+            desc,
+            layouts: Vec::new(),
+            pipeline_layout: None,
+            pipeline: None,
+            bound: Vec::new(),
+        }));
+    }
+
+    /// Replace the shader defines of a pass, the next `compile` builds its pipeline unless
+    /// this combination was used before
+    pub fn set_defines(
+        &mut self,
+        label: &'static str,
+        defines: Vec<&'static str>,
+    ) -> Result<(), GraphError> {
+        self.pass_mut(label)?.desc.defines = defines;
+        Ok(())
+    }
+
+    /// Replace the workgroup size of a pass, see `set_defines`
+    pub fn set_workgroup_size(
+        &mut self,
+        label: &'static str,
+        size: (u32, u32),
+    ) -> Result<(), GraphError> {
+        self.pass_mut(label)?.desc.workgroup_size = size;
+        Ok(())
+    }
+
+    /// Replace the override constants of a pass, see `set_defines`
+    pub fn set_constants(
+        &mut self,
+        label: &'static str,
+        constants: Vec<(&'static str, f64)>,
+    ) -> Result<(), GraphError> {
+        self.pass_mut(label)?.desc.constants = constants;
+        Ok(())
+    }
+
+    // Compute pass called `label`
+    fn pass_mut(&mut self, label: &'static str) -> Result<&mut ComputePass, GraphError> {
+        self.compute_passes_mut()
+            .find(|pass| pass.desc.label == label)
+            .ok_or(GraphError::UnknownPass(label))
+    }
+
+    fn compute_passes_mut(&mut self) -> impl Iterator<Item = &mut ComputePass> {
+        self.passes.iter_mut().filter_map(|node| match node {
+            Node::Compute(pass) => Some(pass),
+            Node::Render(_) => None,
+        })
+    }
+
+    pub fn buffer(&self, name: &str) -> Option<&wgpu::Buffer> {
+        match self.resources.get(name)?.allocation.as_ref()? {
+            Allocation::Buffer(buf, _) => Some(buf),
+            _ => None,
+        }
+    }
+
+    pub fn texture(&self, name: &str) -> Option<&wgpu::Texture> {
+        match self.resources.get(name)?.allocation.as_ref()? {
+            Allocation::Texture(tex, _) => Some(tex),
+            _ => None,
+        }
+    }
+
+    pub fn texture_view(&self, name: &str) -> Option<&wgpu::TextureView> {
+        match self.resources.get(name)?.allocation.as_ref()? {
+            Allocation::Texture(_, view) | Allocation::View(view) => Some(view),
+            _ => None,
+        }
+    }
+
+    /// Create the layouts of new passes and the pipelines of the specializations not built
+    /// yet
+    pub fn compile(
+        &mut self,
+        device: &wgpu::Device,
+        composer: &Composer,
+    ) -> Result<(), GraphError> {
+        let limits = device.limits();
+        for node in self.passes.iter_mut() {
+            let pass = match node {
+                Node::Compute(pass) => pass,
+                Node::Render(pass) => {
+                    if pass.pipeline.is_none() {
+                        pass.compile(device, composer, &self.resources)?;
+                    }
+                    continue;
+                }
+            };
+            let key = PipelineKey::of(&pass.desc);
+            if pass.pipelines.contains_key(&key) {
+                continue;
+            }
+            let (wx, wy) = pass.desc.workgroup_size;
+            if wx == 0
+                || wy == 0
+                || wx > limits.max_compute_workgroup_size_x
+                || wy > limits.max_compute_workgroup_size_y
+                || wx * wy > limits.max_compute_invocations_per_workgroup
+            {
+                return Err(GraphError::WorkgroupSize {
+                    pass: pass.desc.label,
+                    size: pass.desc.workgroup_size,
+                });
+            }
+
+            if pass.pipeline_layout.is_none() {
+                let layouts = group_layouts(
+                    device,
+                    &self.resources,
+                    pass.desc.label,
+                    &pass.desc.groups,
+                    wgpu::ShaderStages::COMPUTE,
+                )?;
+                let layout_refs: Vec<&wgpu::BindGroupLayout> = layouts.iter().collect();
+                pass.pipeline_layout =
+                    Some(device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                        label: Some(pass.desc.label),
+                        bind_group_layouts: &layout_refs,
+                        push_constant_ranges: &[],
+                    }));
+                pass.layouts = layouts;
+            }
+
+            if !pass.modules.contains_key(&pass.desc.defines) {
+                let (module, _) = pass.create_module(device, composer, &pass.desc.defines)?;
+                pass.modules.insert(pass.desc.defines.clone(), module);
+            }
+            let pipeline = pass.create_pipeline(device, &pass.modules[&key.defines], &key);
+            pass.pipelines.insert(key, pipeline);
+        }
+        Ok(())
+    }
+
+    /// Build every module and pipeline created so far again, from the modules of `composer`.
+    /// The graph keeps its pipelines until the result is handed to `install`, so the caller
+    /// can check it in an error scope first
+    pub fn rebuild(
+        &self,
+        device: &wgpu::Device,
+        composer: &Composer,
+    ) -> Result<Rebuild, GraphError> {
+        let mut rebuild = Rebuild {
+            passes: Vec::with_capacity(self.passes.len()),
+            modules: Vec::new(),
+        };
+        for node in self.passes.iter() {
+            let pass = match node {
+                Node::Compute(pass) => pass,
+                Node::Render(pass) if pass.pipeline.is_none() => {
+                    rebuild.passes.push(Rebuilt::Render(None));
+                    continue;
+                }
+                Node::Render(pass) => {
+                    let (module, composed) = pass.create_module(device, composer)?;
+                    let pipeline =
+                        pass.create_pipeline(device, &module, pass.format(&self.resources)?);
+                    rebuild.modules.push((module, composed));
+                    rebuild.passes.push(Rebuilt::Render(Some(pipeline)));
+                    continue;
+                }
+            };
+            let mut modules = HashMap::new();
+            for defines in pass.modules.keys() {
+                let (module, composed) = pass.create_module(device, composer, defines)?;
+                modules.insert(defines.clone(), module.clone());
+                rebuild.modules.push((module, composed));
+            }
+            let pipelines = pass
+                .pipelines
+                .keys()
+                .map(|key| {
+                    let pipeline = pass.create_pipeline(device, &modules[&key.defines], key);
+                    (key.clone(), pipeline)
+                })
+                .collect();
+            rebuild.passes.push(Rebuilt::Compute(modules, pipelines));
+        }
+        Ok(rebuild)
+    }
+
+    /// Swap in the pipelines of `rebuild`, the layouts stay so do the bind groups
+    pub fn install(&mut self, rebuild: Rebuild) {
+        for (node, rebuilt) in self.passes.iter_mut().zip(rebuild.passes) {
+            match (node, rebuilt) {
+                (Node::Compute(pass), Rebuilt::Compute(modules, pipelines)) => {
+                    pass.modules = modules;
+                    pass.pipelines = pipelines;
+                }
+                (Node::Render(pass), Rebuilt::Render(Some(pipeline))) => {
+                    pass.pipeline = Some(pipeline);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// (Re)allocate graph owned resources for a new frame size, buffers only grow while
+    /// textures always match the frame
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        let first_alloc = self.resources.values().any(|res| {
+            res.allocation.is_none()
+                && matches!(
+                    res.kind,
+                    ResourceKind::Buffer { .. } | ResourceKind::Texture { .. }
+                )
+        });
+        if self.size == (width, height) && !first_alloc {
+            return;
+        }
+        self.size = (width, height);
+        let num_pixels = width as u64 * height as u64;
+
+        for (name, resource) in self.resources.iter_mut() {
+            let allocation = match &resource.kind {
+                ResourceKind::Imported | ResourceKind::Target(_) => continue,
+                ResourceKind::Buffer {
+                    bytes_per_pixel,
+                    usage,
+                } => {
+                    let size = bytes_per_pixel * num_pixels;
+                    // Going back and forth between resolutions keeps the largest buffer,
+                    // bound to the part the frame uses
+                    if let Some(Allocation::Buffer(buf, used)) = resource.allocation.as_mut() {
+                        if buf.size() >= size {
+                            *used = wgpu::BufferSize::new(size);
+                            continue;
+                        }
+                    }
+                    Allocation::Buffer(device.create_buffer(&wgpu::BufferDescriptor {
+                        label: Some(name),
+                        size,
+                        usage: *usage,
+                        mapped_at_creation: false,
+                    }), None)
+                }
+                // Unlike a buffer binding a view can't cover part of a texture, the blit samples
+                // and the surface copies would all have to crop it. A few bytes per pixel are
+                // cheap to reallocate next to the buffers
+                ResourceKind::Texture { format, usage } => {
+                    let texture = device.create_texture(&wgpu::TextureDescriptor {
+                        label: Some(name),
+                        size: wgpu::Extent3d {
+                            width,
+                            height,
+                            depth_or_array_layers: 1,
+                        },
+                        mip_level_count: 1,
+                        sample_count: 1,
+                        format: *format,
+                        usage: *usage,
+                        dimension: wgpu::TextureDimension::D2,
+                        view_formats: &[],
+                    });
+                    let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+                    Allocation::Texture(texture, view)
+                }
+            };
+            match resource.allocation.replace(allocation) {
+                Some(Allocation::Buffer(buf, _)) => buf.destroy(),
+                Some(Allocation::Texture(tex, _)) => tex.destroy(),
+                _ => {}
+            }
+        }
+        self.unbind();
+    }
+
+    /// Record every pass, in order, into `encoder`
+    pub fn execute(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+    ) -> Result<(), GraphError> {
+        for node in self.passes.iter_mut() {
+            match node {
+                Node::Compute(pass) => pass.record(
+                    device,
+                    &self.resources,
+                    &mut self.bind_groups,
+                    encoder,
+                    self.size,
+                )?,
+                Node::Render(pass) => {
+                    pass.record(device, &self.resources, &mut self.bind_groups, encoder)?
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Resources were replaced, every pass binds them again
+    fn unbind(&mut self) {
+        self.bind_groups.clear();
+        for node in self.passes.iter_mut() {
+            match node {
+                Node::Compute(pass) => pass.bound.clear(),
+                Node::Render(pass) => pass.bound.clear(),
+            }
+        }
+    }
+
+    fn declare(&mut self, name: &'static str, kind: ResourceKind) {
+        self.resources.insert(
+            name,
+            Resource {
+                kind,
+                allocation: None,
+            },
+        );
+    }
+}
+
+fn create_module(
+    device: &wgpu::Device,
+    composer: &Composer,
+    pass: &'static str,
+    shader: &'static str,
+    defines: &[&'static str],
+) -> Result<(wgpu::ShaderModule, Composed), GraphError> {
+    let composed = composer
+        .compose_mapped(shader, defines)
+        .map_err(|error| GraphError::Shader { pass, error })?;
+    let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some(shader),
+        source: wgpu::ShaderSource::Wgsl(composed.source.as_str().into()),
+    });
+    Ok((module, composed))
+}
+
+// Layouts of the bind groups of a pass, visible to `stages`
+fn group_layouts(
+    device: &wgpu::Device,
+    resources: &HashMap<&'static str, Resource>,
+    pass: &'static str,
+    groups: &[Vec<Slot>],
+    stages: wgpu::ShaderStages,
+) -> Result<Vec<wgpu::BindGroupLayout>, GraphError> {
+    let mut layouts = Vec::with_capacity(groups.len());
+    for group in groups.iter() {
+        let mut entries = Vec::with_capacity(group.len());
+        for slot in group.iter() {
+            let resource = resources
+                .get(slot.resource)
+                .ok_or(GraphError::UnknownResource {
+                    pass,
+                    resource: slot.resource,
+                })?;
+            let mut entry = match (&resource.kind, slot.access) {
+                (ResourceKind::Texture { format, .. }, Access::Write) => {
+                    binding::img_texture_entry(slot.binding, *format)
+                }
+                (ResourceKind::Texture { .. } | ResourceKind::Imported, Access::Sampled) => {
+                    binding::sampled_texture_entry(slot.binding)
+                }
+                (ResourceKind::Imported, Access::Sampler) => binding::sampler_entry(slot.binding),
+                (ResourceKind::Buffer { .. } | ResourceKind::Imported, Access::Uniform) => {
+                    binding::uniform_entry(slot.binding)
+                }
+                (ResourceKind::Buffer { .. } | ResourceKind::Imported, Access::Read) => {
+                    binding::buf_entry(slot.binding, true)
+                }
+                (ResourceKind::Buffer { .. } | ResourceKind::Imported, Access::ReadWrite) => {
+                    binding::buf_entry(slot.binding, false)
+                }
+                (_, access) => {
+                    return Err(GraphError::BadAccess {
+                        pass,
+                        resource: slot.resource,
+                        access,
+                    });
+                }
+            };
+            entry.visibility = stages;
+            entries.push(entry);
+        }
+        layouts.push(binding::group_lay(device, Some(pass), &entries));
+    }
+    Ok(layouts)
+}
+
+// Bind groups of a pass for the current allocations, cached in `cache`
+fn bind_groups(
+    device: &wgpu::Device,
+    resources: &HashMap<&'static str, Resource>,
+    cache: &mut BindGroupCache,
+    layouts: &[wgpu::BindGroupLayout],
+    groups: &[Vec<Slot>],
+) -> Result<Vec<wgpu::BindGroup>, GraphError> {
+    let mut bound = Vec::with_capacity(layouts.len());
+    for (layout, slots) in layouts.iter().zip(groups.iter()) {
+        let mut entries = Vec::with_capacity(slots.len());
+        for slot in slots.iter() {
+            let key = match resources
+                .get(slot.resource)
+                .and_then(|res| res.allocation.as_ref())
+            {
+                Some(Allocation::Buffer(buf, None)) => BindKey::Buffer(buf.clone()),
+                Some(Allocation::Buffer(buf, Some(size))) => {
+                    BindKey::BufferRange(buf.clone(), *size)
+                }
+                Some(Allocation::Texture(_, view) | Allocation::View(view)) => {
+                    BindKey::TextureView(view.clone())
+                }
+                Some(Allocation::Sampler(sampler)) => BindKey::Sampler(sampler.clone()),
+                None => return Err(GraphError::MissingResource(slot.resource)),
+            };
+            entries.push((slot.binding, key));
+        }
+        bound.push(cache.get(device, layout, &entries));
+    }
+    Ok(bound)
+}
@@ -0,0 +1,199 @@
+use std::io::{Seek, Write};
+
+use crate::renderer::{Image, Renderer, RenderLayers, SCENE_KEYWORD};
+use crate::scene::SceneDesc;
+use crate::settings::RenderSettings;
+
+/// Where the offscreen renderer looks for an adapter
+#[derive(Clone, Debug)]
+pub struct HeadlessOptions {
+    pub backends: wgpu::Backends,
+    // Only accept a software adapter, e.g. llvmpipe or WARP
+    pub force_fallback_adapter: bool,
+}
+
+impl Default for HeadlessOptions {
+    fn default() -> Self {
+        Self {
+            // WGPU_BACKEND can narrow this down
+            backends: wgpu::Backends::from_env().unwrap_or(wgpu::Backends::all()),
+            force_fallback_adapter: false,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum HeadlessError {
+    NoAdapter,
+    RequestDevice(wgpu::RequestDeviceError),
+    InvalidSize { width: u32, height: u32 },
+    Readback(wgpu::BufferAsyncError),
+}
+
+impl std::fmt::Display for HeadlessError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HeadlessError::NoAdapter => write!(f, "no suitable GPU adapter"),
+            HeadlessError::RequestDevice(e) => write!(f, "could not create device: {e}"),
+            HeadlessError::InvalidSize { width, height } => {
+                write!(f, "image size {width}x{height} is empty or over the device limits")
+            }
+            HeadlessError::Readback(e) => write!(f, "could not read the frame back: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for HeadlessError {}
+
+/// Create a renderer without any window or surface
+pub async fn create_renderer(options: &HeadlessOptions) -> Result<Renderer, HeadlessError> {
+    let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor {
+        backends: options.backends,
+        flags: wgpu::InstanceFlags::from_build_config().with_env(),
+        ..Default::default()
+    });
+
+    let adapter = instance
+        .request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::default(),
+            compatible_surface: None,
+            force_fallback_adapter: options.force_fallback_adapter,
+        })
+        .await
+        .ok_or(HeadlessError::NoAdapter)?;
+    log::info!("Headless adapter: {:?}", adapter.get_info());
+
+    let (device, queue) = Renderer::request_device(&adapter)
+        .await
+        .map_err(HeadlessError::RequestDevice)?;
+    Ok(Renderer::new(device, queue))
+}
+
+// Accumulate the `spp` samples of the renderer settings
+fn accumulate(renderer: &mut Renderer, mut progress: impl FnMut(u32, u32)) {
+    let spp = renderer.settings().spp.max(1);
+    renderer.reset_accumulation();
+    for sample in 0..spp {
+        renderer.render_offscreen();
+        // Wait for the GPU so the progress means something
+        renderer.device().poll(wgpu::Maintain::Wait);
+        progress(sample + 1, spp);
+    }
+}
+
+/// Accumulate the `spp` samples of the renderer settings and read the frame back.
+/// `progress` is called with the samples done and the total after every sample
+pub async fn render_samples(
+    renderer: &mut Renderer,
+    progress: impl FnMut(u32, u32),
+) -> Result<Image, HeadlessError> {
+    accumulate(renderer, progress);
+    renderer.read_frame().await.map_err(HeadlessError::Readback)
+}
+
+/// Like `render_samples`, but reads back the linear frame and the primary hit layers
+pub async fn render_layers(
+    renderer: &mut Renderer,
+    progress: impl FnMut(u32, u32),
+) -> Result<RenderLayers, HeadlessError> {
+    accumulate(renderer, progress);
+    renderer
+        .request_layers()
+        .await
+        .map_err(HeadlessError::Readback)
+}
+
+/// Sample type of the color channels of an EXR file
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum ExrPrecision {
+    #[default]
+    Half,
+    Float,
+}
+
+/// What goes into an EXR file besides the beauty
+#[derive(Clone, Debug, Default)]
+pub struct ExrOptions {
+    // Of the beauty and albedo channels
+    pub precision: ExrPrecision,
+    pub albedo: bool,
+    pub normal: bool,
+    pub depth: bool,
+    pub object_id: bool,
+}
+
+/// Encode `layers` as a single part OpenEXR file. The beauty goes in `R`, `G`, `B` and `A`,
+/// the chosen AOVs in `albedo.R/G/B`, `normal.X/Y/Z`, `depth.Z` and `object.id`, which Nuke
+/// and most compositors split into layers. Normals and depth are 32 bit floats whatever the
+/// precision and ids 32 bit integers. With a scene, its JSON goes into a string attribute
+pub fn write_exr(
+    layers: &RenderLayers,
+    options: &ExrOptions,
+    scene: Option<&SceneDesc>,
+    out: impl Write + Seek,
+) -> Result<(), exr::error::Error> {
+    use exr::prelude::*;
+
+    let color = |values: Vec<f32>| match options.precision {
+        ExrPrecision::Half => FlatSamples::F16(values.into_iter().map(f16::from_f32).collect()),
+        ExrPrecision::Float => FlatSamples::F32(values),
+    };
+    let component = |pixels: &[[f32; 3]], i: usize| pixels.iter().map(|p| p[i]).collect();
+
+    let mut channels = Vec::new();
+    for (i, name) in ["R", "G", "B", "A"].into_iter().enumerate() {
+        let values = layers.beauty.iter().map(|p| p[i]).collect();
+        channels.push(AnyChannel::new(name, color(values)));
+    }
+    if options.albedo {
+        for (i, name) in ["albedo.R", "albedo.G", "albedo.B"].into_iter().enumerate() {
+            channels.push(AnyChannel::new(name, color(component(&layers.albedo, i))));
+        }
+    }
+    if options.normal {
+        for (i, name) in ["normal.X", "normal.Y", "normal.Z"].into_iter().enumerate() {
+            let values = FlatSamples::F32(component(&layers.normal, i));
+            channels.push(AnyChannel::new(name, values));
+        }
+    }
+    if options.depth {
+        let values = FlatSamples::F32(layers.depth.clone());
+        channels.push(AnyChannel::new("depth.Z", values));
+    }
+    if options.object_id {
+        let values = FlatSamples::U32(layers.object_id.clone());
+        channels.push(AnyChannel::new("object.id", values));
+    }
+
+    let size = (layers.width as usize, layers.height as usize);
+    let mut attributes = LayerAttributes::default();
+    if let Some(scene) = scene {
+        let json = Text::from_bytes_unchecked(scene.to_json().as_bytes().into());
+        attributes
+            .other
+            .insert(Text::from(SCENE_KEYWORD), AttributeValue::Text(json));
+    }
+    let layer = Layer::new(
+        size,
+        attributes,
+        Encoding::FAST_LOSSLESS,
+        AnyChannels::sort(channels.into()),
+    );
+    Image::from_layer(layer).write().to_buffered(out)
+}
+
+/// Render the default world offscreen and return the frame as RGBA8
+pub async fn render_image(
+    width: u32,
+    height: u32,
+    settings: &RenderSettings,
+    options: &HeadlessOptions,
+) -> Result<Image, HeadlessError> {
+    let mut renderer = create_renderer(options).await?;
+    renderer.make_world();
+    renderer.set_settings(*settings);
+    if !renderer.resize(width, height) {
+        return Err(HeadlessError::InvalidSize { width, height });
+    }
+    render_samples(&mut renderer, |_, _| {}).await
+}
@@ -12,13 +12,31 @@ pub struct Ray {
 #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct HitRecord {
     // Vec4 containing vec3 as normal and an extra float as the t value
-    point: [f32; 4],
-    normal: [f32; 3],
+    pub(crate) point: [f32; 4],
+    pub(crate) normal: [f32; 3],
     // 0x1 means backface
     flags: u32,
-    material_id: i32,
-    _pad0: [u32; 3],
+    pub(crate) material_id: i32,
+    // Index of the sphere hit in the sphere buffer
+    pub(crate) object_id: u32,
+    _pad0: [u32; 2],
 }
 
-const _: () = assert!(std::mem::size_of::<HitRecord>() % 16 == 0);
-const _: () = assert!(std::mem::size_of::<Ray>() % 16 == 0);
+const _: () = assert!(std::mem::size_of::<HitRecord>().is_multiple_of(16));
+const _: () = assert!(std::mem::size_of::<Ray>().is_multiple_of(16));
+
+crate::wgsl_struct!(Ray as "Ray" {
+    dir: "vec3<f32>",
+    _pad0: "u32",
+    o: "vec3<f32>",
+    _pad1: "u32",
+});
+
+crate::wgsl_struct!(HitRecord as "HitRecord" {
+    point: "vec4<f32>",
+    normal: "vec3<f32>",
+    flags: "u32",
+    material_id: "i32",
+    object_id: "u32",
+    _pad0: "array<u32, 2>",
+});
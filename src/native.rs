@@ -0,0 +1,26 @@
+use std::path::PathBuf;
+
+use winit::event_loop::EventLoop;
+
+use crate::app::{App, AppEvent, ViewerRequest};
+
+/// Open the viewer in a desktop window and block until it is closed.
+/// Shows the scene file at `scene`, or the demo scene.
+/// `GPUDEMO_RENDER_SCALE` sets the render scale, see `Viewer::set_render_scale`
+pub fn run(scene: Option<PathBuf>) -> Result<(), winit::error::EventLoopError> {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn")).init();
+
+    let event_loop = EventLoop::<AppEvent>::with_user_event().build()?;
+    let scene = scene.map(|path| path.to_string_lossy().into_owned());
+    let mut my_app = App::new(event_loop.create_proxy());
+    let mut request = ViewerRequest::new(scene);
+    if let Some(scale) = std::env::var("GPUDEMO_RENDER_SCALE")
+        .ok()
+        .and_then(|scale| scale.parse().ok())
+    {
+        request = request.with_render_scale(scale);
+    }
+    my_app.add_viewer(request);
+
+    event_loop.run_app(&mut my_app)
+}
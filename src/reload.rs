@@ -0,0 +1,63 @@
+//! Shader hot reloading of development builds: the viewers read the shader files under
+//! `www/public/shaders` again and again, and rebuild their pipelines when one changed.
+//! The web build fetches them from `public/shaders/` next to the page
+
+use std::collections::HashMap;
+
+use web_time::Duration;
+
+use crate::shader::{self, Composer};
+
+/// Between two reads of the shader files
+pub const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Last seen source of every shader file
+pub struct ShaderWatcher {
+    // Starts from the sources baked in, files edited before the start load on the first poll
+    sources: HashMap<&'static str, String>,
+}
+
+impl ShaderWatcher {
+    pub fn new() -> Self {
+        let composer = Composer::builtin();
+        Self {
+            sources: shader::builtin_files()
+                .filter_map(|name| Some((name, composer.module(name)?.to_string())))
+                .collect(),
+        }
+    }
+
+    /// Read every shader file, returns the ones that changed since the last poll with their
+    /// new source. Files that can't be read are skipped
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn poll(&mut self) -> Vec<(String, String)> {
+        let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("www/public/shaders");
+        let read = shader::builtin_files()
+            .filter_map(|name| Some((name, std::fs::read_to_string(dir.join(name)).ok()?)))
+            .collect();
+        self.changes(read)
+    }
+
+    /// Fetch every shader file, see the native `poll`
+    #[cfg(target_arch = "wasm32")]
+    pub async fn poll(&mut self) -> Vec<(String, String)> {
+        let mut read = Vec::new();
+        for name in shader::builtin_files() {
+            match crate::web::fetch_text_uncached(&format!("public/shaders/{name}")).await {
+                Ok(source) => read.push((name, source)),
+                Err(e) => log::debug!("{name}: {e:?}"),
+            }
+        }
+        self.changes(read)
+    }
+
+    fn changes(&mut self, read: Vec<(&'static str, String)>) -> Vec<(String, String)> {
+        read.into_iter()
+            .filter_map(|(name, source)| {
+                let previous = self.sources.insert(name, source.clone());
+                (previous.as_ref() != Some(&source)).then(|| (name.to_string(), source))
+            })
+            .collect()
+    }
+}
+
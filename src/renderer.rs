@@ -1,43 +1,198 @@
-use wasm_bindgen::prelude::*;
-use std::{iter, sync::Arc};
-use wgpu::{include_wgsl, util::DeviceExt};
-
-use winit::window::Window; 
+use std::iter;
+use wgpu::util::DeviceExt;
 
 use crate::camera::{Camera, CameraLean};
 use crate::sphere::{Sphere, Material};
 
 use crate::intersection::{ Ray, HitRecord };
-use crate::binding;
+use crate::graph::{Access, GraphError, PassDesc, Rebuild, RenderGraph, Slot};
+use crate::shader::{Composed, Composer};
+use crate::scene::{CameraDesc, EnvironmentDesc, SceneDesc, SceneError};
+use crate::animation::{AnimationDesc, Timeline};
+use crate::settings::{FrameUniform, RenderSettings, Specialization};
+use crate::world::Scene;
+
+/// Pixels read back from the frame texture, rows are tightly packed RGBA8
+#[derive(Clone, Debug)]
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<u8>,
+}
+
+/// Linear frame and primary hit layers of a render, for compositing. Rows are tightly
+/// packed from the top, pixels where the camera ray escaped hold black, an infinite depth
+/// and object id 0
+#[derive(Clone, Debug)]
+pub struct RenderLayers {
+    pub width: u32,
+    pub height: u32,
+    // Average of the samples, linear RGBA
+    pub beauty: Vec<[f32; 4]>,
+    // Of the material at the primary hit
+    pub albedo: Vec<[f32; 3]>,
+    // World space, facing the camera
+    pub normal: Vec<[f32; 3]>,
+    // Distance along the view direction
+    pub depth: Vec<f32>,
+    // `SphereHandle::id` plus one
+    pub object_id: Vec<u32>,
+}
+
+// Where the camera ray of a pixel hit the scene
+pub(crate) struct PrimaryHit {
+    pub point: [f32; 3],
+    pub normal: [f32; 3],
+    pub material_id: i32,
+    // Index into `Scene::spheres`
+    pub slot: usize,
+}
+
+impl RenderLayers {
+    // `sum` of `samples` samples and the primary hits of the pixels, seen from `view`
+    pub(crate) fn from_hits(
+        width: u32,
+        height: u32,
+        samples: u32,
+        sum: &[[f32; 4]],
+        hits: impl Iterator<Item = Option<PrimaryHit>>,
+        view: &CameraDesc,
+        scene: &Scene,
+    ) -> Self {
+        let n = samples.max(1) as f32;
+        let beauty = sum.iter().map(|s| s.map(|c| c / n)).collect();
+        let forward = [0, 1, 2].map(|i| view.look_at[i] - view.position[i]);
+        let length = forward.iter().map(|v| v * v).sum::<f32>().sqrt().max(f32::EPSILON);
+
+        let pixels = (width * height) as usize;
+        let mut layers = Self {
+            width,
+            height,
+            beauty,
+            albedo: Vec::with_capacity(pixels),
+            normal: Vec::with_capacity(pixels),
+            depth: Vec::with_capacity(pixels),
+            object_id: Vec::with_capacity(pixels),
+        };
+        for hit in hits.take(pixels) {
+            let material = hit
+                .as_ref()
+                .and_then(|hit| scene.materials().get(usize::try_from(hit.material_id).ok()?));
+            let handle = hit
+                .as_ref()
+                .and_then(|hit| scene.sphere_handles().get(hit.slot));
+            let (Some(hit), Some(material), Some(handle)) = (hit, material, handle) else {
+                layers.albedo.push([0.0; 3]);
+                layers.normal.push([0.0; 3]);
+                layers.depth.push(f32::INFINITY);
+                layers.object_id.push(0);
+                continue;
+            };
+            let albedo = material.albedo;
+            let offset = [0, 1, 2].map(|i| hit.point[i] - view.position[i]);
+            layers.albedo.push([albedo[0], albedo[1], albedo[2]]);
+            layers.normal.push(hit.normal);
+            layers
+                .depth
+                .push((0..3).map(|i| offset[i] * forward[i]).sum::<f32>() / length);
+            layers.object_id.push(handle.id() + 1);
+        }
+        layers
+    }
+}
+
+/// Key of the scene file embedded in PNG tEXt chunks, see `Image::to_png`, and in EXR
+/// attributes, see `headless::write_exr`
+pub const SCENE_KEYWORD: &str = "gpudemo scene";
+
+#[cfg(any(feature = "headless", feature = "native", feature = "web"))]
+impl Image {
+    /// Encode as PNG. With a scene, its JSON goes into a tEXt chunk so the render can be
+    /// reproduced from the image alone
+    pub fn to_png(&self, scene: Option<&SceneDesc>) -> Result<Vec<u8>, png::EncodingError> {
+        let mut bytes = Vec::new();
+        let mut encoder = png::Encoder::new(&mut bytes, self.width, self.height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        if let Some(scene) = scene {
+            encoder.add_text_chunk(SCENE_KEYWORD.into(), scene.to_json())?;
+        }
+        encoder.write_header()?.write_image_data(&self.data)?;
+        Ok(bytes)
+    }
+}
+
+/// Pipelines built from edited shaders, see `Renderer::rebuild_shaders`
+#[cfg_attr(not(any(feature = "web", feature = "native")), allow(dead_code))]
+pub(crate) struct ShaderUpdate {
+    composer: Composer,
+    rebuild: Rebuild,
+    // Of the other graphs passed to `rebuild_shaders`, in order
+    others: Vec<Rebuild>,
+}
 
+// `module:line:column: message`, then the line with a caret under the column. The line is
+// the one of the module the composed line came from
+#[cfg_attr(not(any(feature = "web", feature = "native")), allow(dead_code))]
+fn compile_error(message: &wgpu::CompilationMessage, composed: &Composed) -> String {
+    // Native messages come with the report of naga, its code frame shows composed lines
+    let text: Vec<&str> = message
+        .message
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.contains(['│', '┌']))
+        .map(|line| line.trim_start_matches("= "))
+        .collect();
+    let text = text.join(": ");
+    let Some((module, line, column, source)) = message.location.and_then(|location| {
+        let line = location.line_number as usize;
+        let (module, module_line) = composed.origin(line)?;
+        let source = composed.source.lines().nth(line - 1)?;
+        Some((module, module_line, location.line_position as usize, source))
+    }) else {
+        return text;
+    };
+    // Keeps the tabs so the caret lines up
+    let indent: String = source
+        .chars()
+        .take(column.saturating_sub(1))
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect();
+    format!("{module}:{line}:{column}: {text}\n    {source}\n    {indent}^")
+}
+
+/// Runs the Ray, Intersect and Shade passes into the frame texture.
+/// Presenting the frame is left to the caller, see `Viewer` for the windowed case
 pub struct Renderer {
     // Wgpu objects
-    surface: wgpu::Surface<'static>,
     device: wgpu::Device,
     queue: wgpu::Queue,
-    config: wgpu::SurfaceConfiguration,
-    compute_pipeline: [Option<wgpu::ComputePipeline>; 4],
+    graph: RenderGraph,
+    shaders: Composer,
 
-    // Buffers and textures
-    // Ray pass
+    // Buffers and textures, rays, hits and the frame texture are owned by the graph
     camera_uniform: Option<wgpu::Buffer>,
-    seed_uniform: Option<wgpu::Buffer>,
+    frame_uniform: Option<wgpu::Buffer>,
     dim_uniform: Option<wgpu::Buffer>,
-    rays_buf: Option<wgpu::Buffer>,
-    // Intersection pass
-    hit_buf: Option<wgpu::Buffer>,
     materials_buf: Option<wgpu::Buffer>,
     spheres_buf: Option<wgpu::Buffer>,
-    // Final texture
-    frame_texture: Option<wgpu::Texture>,
-    frame_texview: Option<wgpu::TextureView>,
 
-    // Materials 
-    materials: Vec<Material>,
+    // Materials and spheres, uploaded lazily by `encode`
+    scene: Scene,
+    // Of the materials of the loaded scene file by id, `scene_desc` names the others
+    material_names: Vec<String>,
     // Misc
-    pub window: Arc<Window>,
     camera: Camera,
-    pub size: winit::dpi::PhysicalSize<u32>,
+    view: CameraDesc,
+    // Animation of the scene file, poses `view` at `settings.time`
+    timeline: Timeline,
+    environment: EnvironmentDesc,
+    settings: RenderSettings,
+    specialization: Specialization,
+    // Samples accumulated since the last reset
+    sample: u32,
+    width: u32,
+    height: u32,
 }
 
 impl Renderer {
@@ -48,93 +203,149 @@ impl Renderer {
     const HIT_REC_BUF_BIND: u32 = 4;
     const DIM_UNIFORM_BIND: u32 = 5;
     const MAT_BUF_BIND: u32 = 6;
-    const SEED_UNIFORM_BIND: u32 = 7;
-
-    fn ray_pipeline(&self) -> Option<&wgpu::ComputePipeline> {
-        self.compute_pipeline[0].as_ref()
-    }
-
-    fn intersect_pipeline(&self) -> Option<&wgpu::ComputePipeline> {
-        self.compute_pipeline[1].as_ref()
-    }
-
-    fn shade_pipeline(&self) -> Option<&wgpu::ComputePipeline> {
-        self.compute_pipeline[2].as_ref()
-    }
-
-    fn set_ray_pipeline(
-        &mut self,
-        pipeline: wgpu::ComputePipeline,
-    ) -> Option<wgpu::ComputePipeline> {
-        self.compute_pipeline[0].replace(pipeline)
-    }
-
-    fn set_intersect_pipeline(
-        &mut self,
-        pipeline: wgpu::ComputePipeline,
-    ) -> Option<wgpu::ComputePipeline> {
-        self.compute_pipeline[1].replace(pipeline)
-    }
-
-    fn set_shade_pipeline(
-        &mut self,
-        pipeline: wgpu::ComputePipeline,
-    ) -> Option<wgpu::ComputePipeline> {
-        self.compute_pipeline[2].replace(pipeline)
-    }
-
-    pub fn window(&self) -> &Window {
-        &self.window
-    }
-
-    #[allow(dead_code)]
-    async fn fetch_shader(shader_path: &str) -> Result<String, JsValue> {
-        use wasm_bindgen_futures::JsFuture;
-        use web_sys::{Request, RequestInit, RequestMode, Response};
-
-        let opts = RequestInit::new();
-        opts.set_method("GET");
-        opts.set_mode(RequestMode::Cors);
-
-        let request = Request::new_with_str_and_init(shader_path, &opts).unwrap();
-
-        let window = web_sys::window().expect("No web window");
-        let resp_value = JsFuture::from(window.fetch_with_request(&request)).await?;
-        let resp: Response = resp_value.dyn_into()?;
-
-        let text = JsFuture::from(resp.text()?).await?;
-        Ok(text.as_string().unwrap())
-    }
+    const FRAME_UNIFORM_BIND: u32 = 7;
+    const ACCUM_BUF_BIND: u32 = 8;
+
+    // Format of the frame texture, must match the storage texture of shade.wgsl
+    pub const FRAME_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8Unorm;
+
+    const RAY_PASS: &'static str = "Ray pass";
+    const INTERSECT_PASS: &'static str = "Intersect pass";
+    const SHADE_PASS: &'static str = "Shading pass";
+
+    // Graph resources
+    const CAMERA: &'static str = "camera";
+    const RAYS: &'static str = "rays";
+    const DIM: &'static str = "dim";
+    const HITS: &'static str = "hits";
+    const SPHERES: &'static str = "spheres";
+    const MATERIALS: &'static str = "materials";
+    const FRAME_UNIFORM: &'static str = "frame_uniform";
+    const ACCUM: &'static str = "accum";
+    const FRAME: &'static str = "frame";
+
+    fn build_graph(color_fmt: wgpu::TextureFormat) -> RenderGraph {
+        let mut graph = RenderGraph::new();
+        for name in [
+            Renderer::CAMERA,
+            Renderer::DIM,
+            Renderer::SPHERES,
+            Renderer::MATERIALS,
+            Renderer::FRAME_UNIFORM,
+        ] {
+            graph.add_import(name);
+        }
+        graph.add_buffer(
+            Renderer::RAYS,
+            std::mem::size_of::<Ray>() as u64,
+            wgpu::BufferUsages::STORAGE,
+        );
+        // Both are read back by `request_layers`
+        graph.add_buffer(
+            Renderer::HITS,
+            std::mem::size_of::<HitRecord>() as u64,
+            wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+        );
+        graph.add_buffer(
+            Renderer::ACCUM,
+            std::mem::size_of::<[f32; 4]>() as u64,
+            wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+        );
+        graph.add_texture(
+            Renderer::FRAME,
+            color_fmt,
+            wgpu::TextureUsages::STORAGE_BINDING
+                | wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_SRC,
+        );
 
-    pub async fn new(window: Window, color_fmt: wgpu::TextureFormat) -> Self {
-        let window = Arc::new(window);
-        let size = window.inner_size();
-        let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor {
-            backends: wgpu::Backends::BROWSER_WEBGPU,
-            flags: wgpu::InstanceFlags::ALLOW_UNDERLYING_NONCOMPLIANT_ADAPTER
-                | wgpu::InstanceFlags::VALIDATION
-                | wgpu::InstanceFlags::DEBUG,
-            ..Default::default()
+        graph.add_pass(PassDesc {
+            label: Renderer::RAY_PASS,
+            shader: "rays.wgsl",
+            defines: vec![],
+            entry_point: "main",
+            workgroup_size: (8, 8),
+            constants: vec![],
+            groups: vec![
+                vec![Slot::new(Renderer::CAMERA_UNIFORM_BIND, Renderer::CAMERA, Access::Uniform)],
+                vec![Slot::new(Renderer::RAYS_BUF_BIND, Renderer::RAYS, Access::ReadWrite)],
+                vec![Slot::new(Renderer::DIM_UNIFORM_BIND, Renderer::DIM, Access::Uniform)],
+            ],
         });
 
-        let surface = instance.create_surface(Arc::clone(&window)).unwrap();
+        graph.add_pass(PassDesc {
+            label: Renderer::INTERSECT_PASS,
+            shader: "intersect.wgsl",
+            defines: vec![],
+            entry_point: "main",
+            workgroup_size: (8, 8),
+            constants: vec![],
+            groups: vec![
+                vec![Slot::new(Renderer::RAYS_BUF_BIND, Renderer::RAYS, Access::ReadWrite)],
+                vec![Slot::new(Renderer::HIT_REC_BUF_BIND, Renderer::HITS, Access::ReadWrite)],
+                vec![Slot::new(Renderer::SPHERE_BUF_BIND, Renderer::SPHERES, Access::Read)],
+                vec![
+                    Slot::new(Renderer::DIM_UNIFORM_BIND, Renderer::DIM, Access::Uniform),
+                    Slot::new(
+                        Renderer::FRAME_UNIFORM_BIND,
+                        Renderer::FRAME_UNIFORM,
+                        Access::Uniform,
+                    ),
+                ],
+            ],
+        });
 
-        let adapter = instance
-            .request_adapter(&wgpu::RequestAdapterOptions {
-                power_preference: wgpu::PowerPreference::default(),
-                compatible_surface: Some(&surface),
-                force_fallback_adapter: false,
-            })
-            .await
-            .unwrap();
+        graph.add_pass(PassDesc {
+            label: Renderer::SHADE_PASS,
+            shader: "shade.wgsl",
+            defines: vec![],
+            entry_point: "main",
+            workgroup_size: (8, 8),
+            constants: vec![],
+            groups: vec![
+                // Bundle hit record and rays
+                vec![
+                    Slot::new(Renderer::HIT_REC_BUF_BIND, Renderer::HITS, Access::ReadWrite),
+                    Slot::new(Renderer::RAYS_BUF_BIND, Renderer::RAYS, Access::ReadWrite),
+                ],
+                vec![
+                    Slot::new(Renderer::SPHERE_BUF_BIND, Renderer::SPHERES, Access::Read),
+                    Slot::new(Renderer::DIM_UNIFORM_BIND, Renderer::DIM, Access::Uniform),
+                ],
+                vec![
+                    Slot::new(Renderer::IMG_TEX_BIND, Renderer::FRAME, Access::Write),
+                    Slot::new(Renderer::ACCUM_BUF_BIND, Renderer::ACCUM, Access::ReadWrite),
+                ],
+                vec![
+                    Slot::new(Renderer::MAT_BUF_BIND, Renderer::MATERIALS, Access::Read),
+                    Slot::new(
+                        Renderer::FRAME_UNIFORM_BIND,
+                        Renderer::FRAME_UNIFORM,
+                        Access::Uniform,
+                    ),
+                ],
+            ],
+        });
+        graph
+    }
 
+    /// Request a device able to run the compute passes from `adapter`
+    pub async fn request_device(
+        adapter: &wgpu::Adapter,
+    ) -> Result<(wgpu::Device, wgpu::Queue), wgpu::RequestDeviceError> {
         let required_features = wgpu::Features::from_bits_truncate(wgpu::Features::empty().bits());
-        
+
         // NOTE: Done temporarily for unoptimize memory footprint of Hit record
-        let mut required_limits = wgpu::Limits::default();
-        required_limits.max_storage_buffer_binding_size = 2147483644;
+        // Software adapters expose less than that, take what is there
+        let required_limits = wgpu::Limits {
+            max_storage_buffer_binding_size: adapter
+                .limits()
+                .max_storage_buffer_binding_size
+                .min(2147483644),
+            ..Default::default()
+        };
 
-        let (device, queue) = adapter
+        adapter
             .request_device(
                 &wgpu::DeviceDescriptor {
                     label: None,
@@ -145,53 +356,226 @@ impl Renderer {
                 None,
             )
             .await
-            .unwrap();
-
-        let surface_caps = surface.get_capabilities(&adapter);
+    }
 
-        let surface_format = color_fmt;
-
-        let config = wgpu::SurfaceConfiguration {
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_DST,
-            format: surface_format,
-            width: size.width.max(1),
-            height: size.height.max(1),
-            present_mode: surface_caps.present_modes[0],
-            alpha_mode: surface_caps.alpha_modes[0],
-            desired_maximum_frame_latency: 2,
-            view_formats: vec![],
-        };
+    pub fn new(device: wgpu::Device, queue: wgpu::Queue) -> Self {
+        let graph = Renderer::build_graph(Renderer::FRAME_FORMAT);
 
         let camera = Camera::new();
-        Self {
-            surface,
+        let mut renderer = Self {
             device,
             queue,
-            config,
-            compute_pipeline: [None, None, None, None],
+            graph,
+            shaders: Composer::builtin(),
             camera_uniform: None,
-            seed_uniform: None,
+            frame_uniform: None,
             dim_uniform: None,
-            rays_buf: None,
-            hit_buf: None,
             materials_buf: None,
             spheres_buf: None,
-            frame_texture: None,
-            frame_texview: None,
-            materials: Vec::new(),
-            window,
+            scene: Scene::new(),
+            material_names: Vec::new(),
             camera,
-            size,
+            view: CameraDesc::default(),
+            timeline: Timeline::default(),
+            environment: EnvironmentDesc::default(),
+            settings: RenderSettings::default(),
+            specialization: Specialization::default(),
+            sample: 0,
+            width: 0,
+            height: 0,
+        };
+        renderer.create_frame_uniform();
+        renderer
+    }
+
+    pub fn device(&self) -> &wgpu::Device {
+        &self.device
+    }
+
+    pub fn queue(&self) -> &wgpu::Queue {
+        &self.queue
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    pub fn frame_texture(&self) -> Option<&wgpu::Texture> {
+        self.graph.texture(Renderer::FRAME)
+    }
+
+    pub fn frame_view(&self) -> Option<&wgpu::TextureView> {
+        self.graph.texture_view(Renderer::FRAME)
+    }
+
+    #[cfg_attr(not(any(feature = "web", feature = "native")), allow(dead_code))]
+    pub(crate) fn shaders(&self) -> &Composer {
+        &self.shaders
+    }
+
+    /// Build the pipelines of the passes again, with `modules` replacing the shader modules
+    /// of the same name. `others` are graphs compiled from `shaders()` outside of the
+    /// renderer, e.g. the viewer blit, rebuilt along. Resolves to the update for
+    /// `install_shaders`, or to the compile and validation errors with the module and line
+    /// they point at. The renderer keeps its pipelines meanwhile, and for good on errors
+    #[cfg_attr(not(any(feature = "web", feature = "native")), allow(dead_code))]
+    pub(crate) fn rebuild_shaders(
+        &self,
+        modules: Vec<(String, String)>,
+        others: &[&RenderGraph],
+    ) -> impl std::future::Future<Output = Result<ShaderUpdate, String>> + 'static {
+        let mut composer = self.shaders.clone();
+        for (name, source) in modules {
+            composer.set_module(&name, source);
+        }
+        self.device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let rebuild = self.graph.rebuild(&self.device, &composer);
+        let others: Result<Vec<_>, _> = others
+            .iter()
+            .map(|graph| graph.rebuild(&self.device, &composer))
+            .collect();
+        let scope = self.device.pop_error_scope();
+        async move {
+            let rebuild = rebuild.map_err(|e| e.to_string())?;
+            let others = others.map_err(|e| e.to_string())?;
+            let mut errors = Vec::new();
+            let modules = rebuild
+                .modules
+                .iter()
+                .chain(others.iter().flat_map(|other| other.modules.iter()));
+            for (module, composed) in modules {
+                let info = module.get_compilation_info().await;
+                let messages = info
+                    .messages
+                    .iter()
+                    .filter(|m| m.message_type == wgpu::CompilationMessageType::Error);
+                for message in messages {
+                    // A module imported by several passes fails in each
+                    let error = compile_error(message, composed);
+                    if !errors.contains(&error) {
+                        errors.push(error);
+                    }
+                }
+            }
+            // Pipelines of a module that did not compile fail too, the module error says more
+            if let Some(error) = scope.await.filter(|_| errors.is_empty()) {
+                errors.push(error.to_string());
+            }
+            if !errors.is_empty() {
+                return Err(errors.join("\n"));
+            }
+            Ok(ShaderUpdate {
+                composer,
+                rebuild,
+                others,
+            })
+        }
+    }
+
+    /// Swap in the pipelines of `rebuild_shaders`, the frame starts over. Returns the
+    /// rebuilds of the other graphs, for their `install`
+    #[cfg_attr(not(any(feature = "web", feature = "native")), allow(dead_code))]
+    pub(crate) fn install_shaders(&mut self, update: ShaderUpdate) -> Vec<Rebuild> {
+        self.shaders = update.composer;
+        self.graph.install(update.rebuild);
+        self.reset_accumulation();
+        update.others
+    }
+
+    pub fn settings(&self) -> &RenderSettings {
+        &self.settings
+    }
+
+    /// Also moves the camera to `settings.time` of the scene animation, when it has one.
+    /// When the pipelines for `deterministic` or `max_bounces` can't be built, those two keep
+    /// their current value
+    pub fn set_settings(&mut self, mut settings: RenderSettings) {
+        let moved = settings.time != self.settings.time;
+        let respecialize = settings.deterministic != self.settings.deterministic
+            || (self.specialization.fixed_bounces
+                && settings.max_bounces != self.settings.max_bounces);
+        if respecialize {
+            let specialization = self.specialization;
+            if let Err(e) = self.specialize(&settings, &specialization) {
+                log::error!("Render graph: {}", e);
+                let current = self.settings;
+                if let Err(e) = self.specialize(&current, &specialization) {
+                    log::error!("Render graph: {}", e);
+                }
+                settings.deterministic = current.deterministic;
+                settings.max_bounces = current.max_bounces;
+            }
+        }
+        self.settings = settings;
+        self.reset_accumulation();
+        if let Some(view) = self.timeline.pose(settings.time).filter(|_| moved) {
+            self.set_view(view);
         }
     }
 
+    pub fn specialization(&self) -> &Specialization {
+        &self.specialization
+    }
+
+    /// Switch the pipelines of the passes to another variant, building the ones never used
+    /// before. False and the current variant is kept when it can't be built, e.g. with a
+    /// workgroup over the device limits
+    pub fn set_specialization(&mut self, specialization: Specialization) -> bool {
+        let settings = self.settings;
+        let Err(e) = self.specialize(&settings, &specialization) else {
+            self.specialization = specialization;
+            return true;
+        };
+        log::error!("Render graph: {}", e);
+        let current = self.specialization;
+        if let Err(e) = self.specialize(&settings, &current) {
+            log::error!("Render graph: {}", e);
+        }
+        false
+    }
+
+    // Defines and override constants of every pass for `settings` and `specialization`
+    fn specialize(
+        &mut self,
+        settings: &RenderSettings,
+        specialization: &Specialization,
+    ) -> Result<(), GraphError> {
+        let [x, y] = specialization.workgroup_size;
+        for pass in [Renderer::RAY_PASS, Renderer::INTERSECT_PASS, Renderer::SHADE_PASS] {
+            self.graph.set_workgroup_size(pass, (x, y))?;
+        }
+        let defines = if settings.deterministic {
+            vec!["DETERMINISTIC"]
+        } else {
+            vec![]
+        };
+        self.graph.set_defines(Renderer::SHADE_PASS, defines)?;
+        let constants = if specialization.fixed_bounces {
+            vec![("MAX_BOUNCES", settings.max_bounces as f64)]
+        } else {
+            vec![]
+        };
+        self.graph.set_constants(Renderer::SHADE_PASS, constants)?;
+        self.graph.compile(&self.device, &self.shaders)
+    }
+
+    /// Number of samples accumulated into the current frame
+    pub fn sample_count(&self) -> u32 {
+        self.sample
+    }
+
+    /// Start accumulating samples from scratch, e.g. after the scene or camera changed
+    pub fn reset_accumulation(&mut self) {
+        self.sample = 0;
+    }
+
     // NOTE: Later we had other parameters to control the number of rays per pixel
     // NOTE: For now one ray per pixel
+    #[allow(dead_code)]
     pub fn num_rays(&self) -> u32 {
-        self.size.width * self.size.height
+        self.width * self.height
     }
 
-    #[allow(dead_code)]
     fn img_bytes_per_row(width: u32) -> u32 {
         let bytes_per_row = std::mem::size_of::<u32>() * width as usize;
         let alignment = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT as usize;
@@ -201,103 +585,48 @@ impl Renderer {
         num as u32
     }
 
-    fn create_rec_buf (&mut self ) {
-        let buffer = vec![0 as u8; self.num_rays() as usize * std::mem::size_of::<HitRecord>()];
-
-        let hit_buf = self
-            .device
-            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some("Hit rec buffer"),
-                contents: &buffer,
-                usage: wgpu::BufferUsages::STORAGE,
-            });
-        if let Some(buf) = self.hit_buf.as_ref() {
-            buf.destroy();
-        }
-        self.hit_buf = Some(hit_buf);
-    }
-
-    fn create_ray_buf (&mut self ) {
-        let buffer = vec![0 as u8; self.num_rays() as usize * std::mem::size_of::<Ray>()];
-
-        let ray_buf = self
-            .device
-            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some("Ray buffer"),
-                contents: &buffer,
-                usage: wgpu::BufferUsages::STORAGE,
-            });
-        if let Some(buf) = self.rays_buf.as_ref() {
-            buf.destroy();
-        }
-        self.rays_buf = Some(ray_buf);
-    }
-
-    fn create_img_texture(&mut self) {
-        let width = self.size.width;
-        let height = self.size.height;
-        let buffer = vec![0 as u8; (width * height) as usize * std::mem::size_of::<u32>()];
-
-        let texture = self.device.create_texture_with_data(
-            &self.queue,
-            &wgpu::TextureDescriptor {
-                label: Some("Storage texture"),
-                size: wgpu::Extent3d {
-                    width,
-                    height,
-                    depth_or_array_layers: 1,
-                },
-                mip_level_count: 1,
-                sample_count: 1,
-                format: self.config.format,
-                usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::COPY_SRC,
-                dimension: wgpu::TextureDimension::D2,
-                view_formats: &[],
-            },
-            wgpu::util::TextureDataOrder::LayerMajor,
-            &buffer,
-        );
-        if let Some(tex) = self.frame_texture.as_ref() {
-            tex.destroy();
-            self.frame_texview = None;
-        }
-        self.frame_texview = Some(texture.create_view(&wgpu::TextureViewDescriptor::default()));
-        self.frame_texture = Some(texture);
-    }
-
     fn create_dim_uniform (&mut self)  {
+        // Resolution switches only rewrite it
+        if let Some(uniform) = self.dim_uniform.as_ref() {
+            self.queue
+                .write_buffer(uniform, 0, bytemuck::cast_slice(&[self.width, self.height]));
+            return;
+        }
 
         let uniform_buf =
             self.device
                 .create_buffer_init(&wgpu::util::BufferInitDescriptor {
                     label: Some("Dimensions uniform"),
-                    contents: bytemuck::cast_slice(&[self.size.width, self.size.height]),
+                    contents: bytemuck::cast_slice(&[self.width, self.height]),
                     usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
                 });
-        if let Some(uniform) = self.dim_uniform.as_ref() {
-            uniform.destroy();
-        }
+        self.graph.import_buffer(Renderer::DIM, uniform_buf.clone());
         self.dim_uniform= Some(uniform_buf);
     }
 
-    fn create_seed_uniform (&mut self)  {
-        let seed: f32 = 3.0;
+    fn create_frame_uniform(&mut self) {
         let uniform_buf =
             self.device
                 .create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                    label: Some("Seed uniform"),
-                    contents: bytemuck::cast_slice(&[seed]),
+                    label: Some("Frame uniform"),
+                    contents: bytemuck::cast_slice(&[FrameUniform::new(&self.settings, &self.environment, 0)]),
                     usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
                 });
-        if let Some(uniform) = self.seed_uniform.as_ref() {
+        if let Some(uniform) = self.frame_uniform.as_ref() {
             uniform.destroy();
         }
-        self.seed_uniform= Some(uniform_buf);
+        self.graph.import_buffer(Renderer::FRAME_UNIFORM, uniform_buf.clone());
+        self.frame_uniform = Some(uniform_buf);
     }
 
-
     fn create_camera_uniform(&mut self) {
         let camera_lean: CameraLean = self.camera.compute_sensor();
+        // Rewritten on every camera move
+        if let Some(uniform) = self.camera_uniform.as_ref() {
+            self.queue
+                .write_buffer(uniform, 0, bytemuck::cast_slice(&[camera_lean]));
+            return;
+        }
         let camera_uniform_buffer =
             self.device
                 .create_buffer_init(&wgpu::util::BufferInitDescriptor {
@@ -305,481 +634,358 @@ impl Renderer {
                     contents: bytemuck::cast_slice(&[camera_lean]),
                     usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
                 });
-        if let Some(uniform) = self.camera_uniform.as_ref() {
-            uniform.destroy();
-        }
+        self.graph.import_buffer(Renderer::CAMERA, camera_uniform_buffer.clone());
         self.camera_uniform= Some(camera_uniform_buffer);
     }
 
+    /// Load the demo scene, `scenes/default.json`
     pub fn make_world(&mut self) {
-        let blue_metal = Material {
-            albedo: [0.1, 0.2, 0.5, 1.0],
-            kind: 1,
-            ..Default::default()
-        };
-        let boring_ground = Material {
-            albedo: [0.7, 0.5, 0.1, 1.0],
-            ..Default::default()
-        };
-        let red_ball = Material {
-            albedo: [0.44, 0.075, 0.05, 1.0],
-            ..Default::default()
-        };
+        self.load_scene(&SceneDesc::default_scene())
+            .expect("Built-in scene");
+    }
 
-        let yello_metal = Material {
-            albedo: [0.5, 0.6, 0.3, 1.0],
-            kind: 1,
-            ..Default::default()
-        };
-
-        let pink_condensate = Material {
-            albedo: [0.5, 0.1, 0.4, 1.0],
-            kind: 1,
-            fuzz: 0.2,
-            ..Default::default()
+    /// Validate a scene and upload its world, camera, environment and settings
+    pub fn load_scene(&mut self, scene: &SceneDesc) -> Result<(), SceneError> {
+        scene.validate()?;
+        let (materials, spheres) = scene.to_world()?;
+        self.set_world(&materials, &spheres)?;
+        self.material_names = scene.materials.keys().cloned().collect();
+        self.environment = scene.environment;
+        self.timeline = Timeline {
+            animation: scene.animation.clone(),
+            camera: scene.camera,
         };
+        self.set_settings(scene.settings);
+        self.set_view(self.timeline.pose(scene.settings.time).unwrap_or(scene.camera));
+        Ok(())
+    }
 
-        let chrome = Material {
-            albedo: [0.8, 0.8, 0.8, 1.0],
-            kind: 1,
-            ..Default::default()
-        };
+    pub fn view(&self) -> &CameraDesc {
+        &self.view
+    }
 
-        self.materials.push(blue_metal);
-        self.materials.push(boring_ground);
-        self.materials.push(red_ball);
-        self.materials.push(yello_metal);
-        self.materials.push(pink_condensate);
-        self.materials.push(chrome);
-
-        #[rustfmt::skip]
-        let top_sphere = Sphere::new(
-             [0.0, 0.0, 500.0],
-             0,
-             50.0,
-        );
-        #[rustfmt::skip]
-        let bottom_sphere  =  Sphere::new(
-             [0.0, -1050.0, 500.0],
-             1,
-             1000.0,
-        );
+    /// Animation of the loaded scene, `settings.time` moves along it
+    pub fn animation(&self) -> &AnimationDesc {
+        &self.timeline.animation
+    }
 
-        let mut spheres = vec![top_sphere, bottom_sphere];
-        #[rustfmt::skip]
-        spheres.push (
-          Sphere::new(
-             [-80.0, -20.0, 300.0],
-             2,
-             50.0)
-        ); 
-        #[rustfmt::skip]
-        spheres.push (
-          Sphere::new(
-             [130.0, 20.0, 350.0],
-             3,
-             80.0)
-        ); 
-
-        #[rustfmt::skip]
-        spheres.push (
-          Sphere::new(
-             [130.0, 200.0, 300.0],
-             5,
-             38.0)
-        ); 
-
-        #[rustfmt::skip]
-        spheres.push (
-          Sphere::new(
-             [-200.0, 20.0, 500.0],
-             4,
-             80.0)
-        ); 
-
-        let buf = self
-            .device
-            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some("Spheres array"),
-                contents: bytemuck::cast_slice(spheres.as_slice()),
-                usage: wgpu::BufferUsages::STORAGE,
-            });
-        if let Some(sphere_buf) = self.spheres_buf.as_ref() {
-            sphere_buf.destroy();
+    /// Move the camera, keeps the picture size
+    pub fn set_view(&mut self, view: CameraDesc) {
+        self.view = view;
+        if self.width > 0 && self.height > 0 {
+            self.camera.set_view(&self.view, self.width, self.height);
+            self.create_camera_uniform();
         }
-        self.spheres_buf = Some(buf);
+        self.reset_accumulation();
+    }
 
-        // Make material buffer
-        let buf = self
-            .device
-            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some("Materials"),
-                contents: bytemuck::cast_slice(self.materials.as_slice()),
-                usage: wgpu::BufferUsages::STORAGE,
-            });
-        if let Some(matbuf) = self.materials_buf.as_ref() {
-            matbuf.destroy();
-        }
-        self.materials_buf = Some(buf);
-
-        self.create_seed_uniform();
-    }
-
-
-    fn create_pipelines(&mut self) {
-        let rays_grp_lay = binding::buf_bind_group_lay(&self.device, Renderer::RAYS_BUF_BIND, false);
-
-        let dim_grp_lay = binding::uniform_bind_group_lay(&self.device, Renderer::DIM_UNIFORM_BIND);
-
-        if self.ray_pipeline().is_none() {
-            let camera_grp_lay =
-                binding::uniform_bind_group_lay(&self.device, Renderer::CAMERA_UNIFORM_BIND);
-
-            let compute_pipeline_layout =
-                self.device
-                    .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                        label: None,
-                        bind_group_layouts: &[&camera_grp_lay, &rays_grp_lay, &dim_grp_lay],
-                        push_constant_ranges: &[],
-                    });
-
-            let shader_desc = include_wgsl!("../www/public/shaders/rays.wgsl");
-            let shader_mod = self.device.create_shader_module(shader_desc);
-            let compute_pipeline =
-                self.device
-                    .create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-                        label: Some("Rays pipeline"),
-                        layout: Some(&compute_pipeline_layout),
-                        module: &shader_mod,
-                        entry_point: Some("main"),
-                        compilation_options: Default::default(),
-                        cache: None,
-                    });
-            let _ = self.set_ray_pipeline(compute_pipeline);
-        }
+    /// Replace the scene with `materials` and `spheres`, rejects them if a sphere could read
+    /// out of bounds
+    pub fn set_world(&mut self, materials: &[Material], spheres: &[Sphere]) -> Result<(), SceneError> {
+        self.scene = Scene::from_world(materials, spheres)?;
+        self.material_names.clear();
+        self.reset_accumulation();
+        Ok(())
+    }
+
+    pub fn scene(&self) -> &Scene {
+        &self.scene
+    }
 
-        let hit_rec_lay =
-            binding::buf_bind_group_lay(&self.device, Renderer::HIT_REC_BUF_BIND, false);
-
-
-
-        if self.intersect_pipeline().is_none() {
-            let sphere_grp_lay =
-                binding::buf_bind_group_lay(&self.device, Renderer::SPHERE_BUF_BIND, true);
-
-            let compute_pipeline_layout =
-                self.device
-                    .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                        label: None,
-                        bind_group_layouts: &[
-                            &rays_grp_lay,
-                            &hit_rec_lay,
-                            &sphere_grp_lay,
-                            &dim_grp_lay,
-                        ],
-                        push_constant_ranges: &[],
-                    });
-
-            let shader_desc = include_wgsl!("../www/public/shaders/intersect.wgsl");
-            let shader_mod = self.device.create_shader_module(shader_desc);
-            let compute_pipeline =
-                self.device
-                    .create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-                        label: Some("Intersect pipeline"),
-                        layout: Some(&compute_pipeline_layout),
-                        module: &shader_mod,
-                        entry_point: Some("main"),
-                        compilation_options: Default::default(),
-                        cache: None,
-                    });
-            let _ = self.set_intersect_pipeline(compute_pipeline);
+    /// Scene file of what is rendered now, camera and settings included.
+    /// An animated camera is posed by `settings.time`, the file keeps the one it starts from
+    pub fn scene_desc(&self) -> SceneDesc {
+        let camera = match self.timeline.animation.is_animated() {
+            true => self.timeline.camera,
+            false => self.view,
+        };
+        SceneDesc {
+            camera,
+            environment: self.environment,
+            settings: self.settings,
+            animation: self.timeline.animation.clone(),
+            ..SceneDesc::from_world(
+                self.scene.materials(),
+                self.scene.spheres(),
+                &self.material_names,
+            )
         }
+    }
 
-        if self.shade_pipeline().is_none() {
-            let mira_lay = binding::mira(
-                &self.device,
-                Renderer::HIT_REC_BUF_BIND,
-                Renderer::RAYS_BUF_BIND,
-            );
-            let frame_tex_lay = binding::img_texture_bind_group_lay(
-                &self.device,
-                self.config.format,
-                Renderer::IMG_TEX_BIND,
-            );
+    /// Edit the scene in place, the changes reach the GPU with the next `encode`
+    pub fn scene_mut(&mut self) -> &mut Scene {
+        &mut self.scene
+    }
 
-            let material_grp_lay = binding::material_n_seed_group_lay(
+    /// Write the edited parts of the scene to the GPU, returns true if anything changed
+    fn flush_scene(&mut self) -> bool {
+        let (dirty_materials, dirty_spheres) = self.scene.take_dirty();
+        let changed = dirty_materials.is_some() || dirty_spheres.is_some();
+        if let Some(dirty) = dirty_materials {
+            let data: &[u8] = bytemuck::cast_slice(self.scene.materials());
+            Renderer::sync_buffer(
                 &self.device,
-                Renderer::MAT_BUF_BIND,
-                Renderer::SEED_UNIFORM_BIND,
-                true,
+                &self.queue,
+                &mut self.graph,
+                Renderer::MATERIALS,
+                &mut self.materials_buf,
+                data,
+                dirty,
+                std::mem::size_of::<Material>(),
             );
-
-            let sphere_dim_grp_lay = binding::sphere_n_dim_group_lay(
+        }
+        if let Some(dirty) = dirty_spheres {
+            let data: &[u8] = bytemuck::cast_slice(self.scene.spheres());
+            Renderer::sync_buffer(
                 &self.device,
-                Renderer::SPHERE_BUF_BIND,
-                Renderer::DIM_UNIFORM_BIND,
-                true,
+                &self.queue,
+                &mut self.graph,
+                Renderer::SPHERES,
+                &mut self.spheres_buf,
+                data,
+                dirty,
+                std::mem::size_of::<Sphere>(),
             );
+        }
+        changed
+    }
 
-
-            let compute_pipeline_layout =
-                self.device
-                    .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                        label: None,
-                        bind_group_layouts: &[
-                            &mira_lay,
-                            &sphere_dim_grp_lay,
-                            &frame_tex_lay,
-                            &material_grp_lay,
-                        ],
-                        push_constant_ranges: &[],
-                    });
-
-            let shader_desc = include_wgsl!("../www/public/shaders/shade.wgsl");
-            let shader_mod = self.device.create_shader_module(shader_desc);
-            let compute_pipeline =
-                self.device
-                    .create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-                        label: Some("Shade pipeline"),
-                        layout: Some(&compute_pipeline_layout),
-                        module: &shader_mod,
-                        entry_point: Some("main"),
-                        compilation_options: Default::default(),
-                        cache: None,
-                    });
-            let _ = self.set_shade_pipeline(compute_pipeline);
-
+    /// Write the `dirty` elements of `data` to `buffer`, or move everything to a buffer twice
+    /// as large when it doesn't fit. The graph binds the used part only
+    #[allow(clippy::too_many_arguments)]
+    fn sync_buffer(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        graph: &mut RenderGraph,
+        name: &'static str,
+        buffer: &mut Option<wgpu::Buffer>,
+        data: &[u8],
+        dirty: std::ops::Range<usize>,
+        stride: usize,
+    ) {
+        // NOTE: An empty binding is invalid, an empty scene binds one zeroed element
+        let used = data.len().max(stride) as u64;
+        let fits = buffer.as_ref().is_some_and(|buf| buf.size() >= used);
+        if !fits {
+            let capacity = buffer
+                .as_ref()
+                .map_or(used, |buf| (buf.size() * 2).max(used));
+            let new_buf = device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(name),
+                size: capacity,
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            });
+            if let Some(old) = buffer.replace(new_buf) {
+                old.destroy();
+            }
         }
+        let buf = buffer.as_ref().unwrap();
+
+        if data.is_empty() {
+            queue.write_buffer(buf, 0, &vec![0; stride]);
+        } else if !fits {
+            queue.write_buffer(buf, 0, data);
+        } else {
+            // Removing the last elements leaves a range past the end
+            let end = (dirty.end * stride).min(data.len());
+            let start = (dirty.start * stride).min(end);
+            if start < end {
+                queue.write_buffer(buf, start as u64, &data[start..end]);
+            }
+        }
+        graph.import_buffer_range(name, buf.clone(), wgpu::BufferSize::new(used).unwrap());
+    }
+
+    /// Whether a frame of `width`x`height` fits in the device limits, its texture and its
+    /// per pixel buffers, the readback of `request_layers` included
+    pub fn fits(&self, width: u32, height: u32) -> bool {
+        let limits = self.device.limits();
+        let pixels = width as u64 * height as u64;
+        let binding = [
+            std::mem::size_of::<Ray>(),
+            std::mem::size_of::<HitRecord>(),
+            std::mem::size_of::<[f32; 4]>(),
+        ]
+        .into_iter()
+        .max()
+        .unwrap() as u64
+            * pixels;
+        let readback =
+            (std::mem::size_of::<[f32; 4]>() + std::mem::size_of::<HitRecord>()) as u64 * pixels;
+        width <= limits.max_texture_dimension_2d
+            && height <= limits.max_texture_dimension_2d
+            && binding <= limits.max_storage_buffer_binding_size as u64
+            && readback <= limits.max_buffer_size
     }
 
-    pub fn on_resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) -> bool {
-        
-        log::warn!("w={}, h={}", new_size.width, new_size.height);
+    /// Resize the frame, false and the frame stays as is when the size is zero or doesn't
+    /// `fit`
+    pub fn resize(&mut self, width: u32, height: u32) -> bool {
+        log::debug!("Resizing to {width}x{height}");
 
-        if new_size.width > 0 && new_size.height > 0 {
-            self.size = new_size;
-            self.config.width = new_size.width;
-            self.config.height = new_size.height;
-            self.surface.configure(&self.device, &self.config);
+        if width > 0 && height > 0 && self.fits(width, height) {
+            self.width = width;
+            self.height = height;
 
-            log::warn!("Building or updating 🛠 buffers");
-            self.camera.set_focal_length(35.0);
-            self.camera.set_resolution(
-                new_size.width,
-                new_size.height,
-                true,
-            );
-            self.camera.position = [0.0, 400.0, -100.0]; 
-            self.camera.look_at = [0.0, 0.0, 500.0];
+            self.camera.set_view(&self.view, width, height);
 
-            self.create_img_texture();
             // NOTE: We could create the buffers, than update the resolution of the camera and dim
             // uniform
             self.create_camera_uniform();
             self.create_dim_uniform();
-            self.create_ray_buf();
-            self.create_rec_buf();
+            self.graph.resize(&self.device, width, height);
+            self.reset_accumulation();
 
-            self.create_pipelines();
+            if let Err(e) = self.graph.compile(&self.device, &self.shaders) {
+                log::error!("Render graph: {}", e);
+                return false;
+            }
             return true;
         }
         false
     }
 
-    fn set_buffer_binding<'a, 'b>(
-        &self,
-        compute_pass: &mut wgpu::ComputePass<'a>,
-        pipeline: &wgpu::ComputePipeline,
-        resource: wgpu::BindingResource<'b>,
-        grp_index: u32,
-        binding: u32,
-    ) {
-        let grp = binding::bind_group_from(
-            &self.device,
-            resource,
-            binding,
-            &pipeline.get_bind_group_layout(grp_index),
+    /// Record the passes accumulating one more sample into the frame texture
+    pub fn encode(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        if self.flush_scene() {
+            self.reset_accumulation();
+        }
+        // The frame is final once it holds its samples, however many frames the caller draws
+        if self.settings.deterministic && self.sample >= self.settings.spp {
+            return;
+        }
+        let frame = FrameUniform::new(&self.settings, &self.environment, self.sample);
+        self.queue.write_buffer(
+            self.frame_uniform.as_ref().unwrap(),
+            0,
+            bytemuck::cast_slice(&[frame]),
         );
-        compute_pass.set_bind_group(grp_index, &grp, &[]);
+        if let Err(e) = self.graph.execute(&self.device, encoder) {
+            log::error!("Render graph: {}", e);
+            return;
+        }
+        self.sample += 1;
     }
 
-    pub fn render (&mut self) -> Result<(), wgpu::SurfaceError>{
-        // log::warn!("Render") ; 
-        let output = self.surface.get_current_texture()?;
-
+    /// Render a frame without presenting it anywhere
+    pub fn render_offscreen(&mut self) {
         let mut encoder = self
             .device
             .create_command_encoder(&wgpu::CommandEncoderDescriptor {
-                label: Some("Render Encoder"),
+                label: Some("Offscreen Encoder"),
             });
+        self.encode(&mut encoder);
+        self.queue.submit(iter::once(encoder.finish()));
+    }
 
-        let width = self.size.width;
-        let height = self.size.height;
-
-        let workgrp_x = width.div_ceil(8);
-        let workgrp_y = height.div_ceil(8);
-
-        // Rays pass #########################################
-        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
-            label: Some("Ray pass"),
-            ..Default::default()
-        });
-        let compute_pipeline = self.ray_pipeline().unwrap();
-
-        compute_pass.set_pipeline(compute_pipeline);
-
-        self.set_buffer_binding(
-            &mut compute_pass,
-            compute_pipeline,
-            self.camera_uniform.as_ref().unwrap().as_entire_binding(),
-            0,
-            Renderer::CAMERA_UNIFORM_BIND,
-        );
-
-        self.set_buffer_binding(
-            &mut compute_pass,
-            compute_pipeline,
-            self.rays_buf.as_ref().unwrap().as_entire_binding(),
-            1,
-            Renderer::RAYS_BUF_BIND,
-        );
-
-        self.set_buffer_binding(
-            &mut compute_pass,
-            compute_pipeline,
-            self.dim_uniform.as_ref().unwrap().as_entire_binding(),
-            2,
-            Renderer::DIM_UNIFORM_BIND,
-        );
-
-        compute_pass.dispatch_workgroups(workgrp_x, workgrp_y, 1);
-        std::mem::drop(compute_pass);
-
-        // Intersection pass ##################################
-
-        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
-            label: Some("Intersect pass"),
-            ..Default::default()
-        });
-        let compute_pipeline = self.intersect_pipeline().unwrap();
-
-        compute_pass.set_pipeline(compute_pipeline);
-
-        self.set_buffer_binding(
-            &mut compute_pass,
-            compute_pipeline,
-            self.rays_buf.as_ref().unwrap().as_entire_binding(),
-            0,
-            Renderer::RAYS_BUF_BIND,
-        );
-
-        self.set_buffer_binding(
-            &mut compute_pass,
-            compute_pipeline,
-            self.hit_buf.as_ref().unwrap().as_entire_binding(),
-            1,
-            Renderer::HIT_REC_BUF_BIND,
-        );
-
-        self.set_buffer_binding(
-            &mut compute_pass,
-            compute_pipeline,
-            self.spheres_buf.as_ref().unwrap().as_entire_binding(),
-            2,
-            Renderer::SPHERE_BUF_BIND,
-        );
-
-        self.set_buffer_binding(
-            &mut compute_pass,
-            compute_pipeline,
-            self.dim_uniform.as_ref().unwrap().as_entire_binding(),
-            3,
-            Renderer::DIM_UNIFORM_BIND,
-        );
-
-        compute_pass.dispatch_workgroups(workgrp_x, workgrp_y, 1);
-        std::mem::drop(compute_pass);
-
-        // Shading pass ##################################
-
-        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
-            label: Some("Shading pass"),
-            ..Default::default()
+    /// Read the sample sum and the primary hits back, see `RenderLayers`.
+    /// Like `request_frame` the copy is submitted right away
+    pub fn request_layers(
+        &self,
+    ) -> impl std::future::Future<Output = Result<RenderLayers, wgpu::BufferAsyncError>> + 'static
+    {
+        let (accum, hits) = self
+            .graph
+            .buffer(Renderer::ACCUM)
+            .zip(self.graph.buffer(Renderer::HITS))
+            .expect("Renderer must be resized before reading layers");
+        let (width, height) = (self.width, self.height);
+        let pixels = width as u64 * height as u64;
+        // The graph may keep larger buffers around after shrinking
+        let accum_size = pixels * std::mem::size_of::<[f32; 4]>() as u64;
+        let hits_size = pixels * std::mem::size_of::<HitRecord>() as u64;
+
+        let staging = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Layers readback"),
+            size: accum_size + hits_size,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
         });
-        let compute_pipeline = self.shade_pipeline().unwrap();
-
-        compute_pass.set_pipeline(compute_pipeline);
-        
-        // Bundle hit record and rays
-        {
-            let grp = binding::mira_bind(
-                &self.device,
-                self.hit_buf.as_ref().unwrap().as_entire_binding(),
-                self.rays_buf.as_ref().unwrap().as_entire_binding(),
-                Renderer::HIT_REC_BUF_BIND,
-                Renderer::RAYS_BUF_BIND,
-                &compute_pipeline.get_bind_group_layout(0),
-            );
-            compute_pass.set_bind_group(0, &grp, &[]);
-        }
-
-
-        // Bind spheres and dim
-        {
-            let grp = binding::sphere_n_dim_bind_group(
-                &self.device,
-                self.spheres_buf.as_ref().unwrap().as_entire_binding(),
-                self.dim_uniform.as_ref().unwrap().as_entire_binding(),
-                Renderer::SPHERE_BUF_BIND,
-                Renderer::DIM_UNIFORM_BIND,
-                &compute_pipeline.get_bind_group_layout(1),
-            );
-            compute_pass.set_bind_group(1, &grp, &[]);
-        }
-
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Layers Readback Encoder"),
+            });
+        encoder.copy_buffer_to_buffer(accum, 0, &staging, 0, accum_size);
+        encoder.copy_buffer_to_buffer(hits, 0, &staging, accum_size, hits_size);
+        self.queue.submit(iter::once(encoder.finish()));
 
-        // Bind materials and seed value
-        {
-            let grp = binding::material_n_seed_bind_group(
-                &self.device,
-                self.materials_buf.as_ref().unwrap().as_entire_binding(),
-                self.seed_uniform.as_ref().unwrap().as_entire_binding(),
-                Renderer::MAT_BUF_BIND,
-                Renderer::SEED_UNIFORM_BIND,
-                &compute_pipeline.get_bind_group_layout(3),
-            );
-            compute_pass.set_bind_group(3, &grp, &[]);
+        let device = self.device.clone();
+        let (samples, view, scene) = (self.sample, self.view, self.scene.clone());
+        async move {
+            let slice = staging.slice(..);
+            let (sender, receiver) = futures_channel::oneshot::channel();
+            slice.map_async(wgpu::MapMode::Read, move |res| {
+                let _ = sender.send(res);
+            });
+            device.poll(wgpu::Maintain::Wait);
+            receiver.await.expect("Readback callback was dropped")?;
+
+            let layers = {
+                let mapped = slice.get_mapped_range();
+                let (sum, records) = mapped.split_at(accum_size as usize);
+                // Mapped ranges are aligned to `wgpu::MAP_ALIGNMENT`
+                let sum: &[[f32; 4]] = bytemuck::cast_slice(sum);
+                let records: &[HitRecord] = bytemuck::cast_slice(records);
+                let hits = records.iter().map(|record| {
+                    let (point, normal) = (record.point, record.normal);
+                    (point[3] > 0.0).then_some(PrimaryHit {
+                        point: [point[0], point[1], point[2]],
+                        normal,
+                        material_id: record.material_id,
+                        slot: record.object_id as usize,
+                    })
+                });
+                RenderLayers::from_hits(width, height, samples, sum, hits, &view, &scene)
+            };
+            staging.unmap();
+            Ok(layers)
         }
+    }
 
-        // Bind texture
-        {
-            let frame_tex_lay = compute_pipeline.get_bind_group_layout(2);
-            let frame_tex_grp = binding::img_texture_bind_group(
-                &self.device,
-                self.frame_texview.as_ref().unwrap(),
-                Renderer::IMG_TEX_BIND,
-                &frame_tex_lay,
-            );
-            compute_pass.set_bind_group(2, &frame_tex_grp, &[]);
-        }
+    /// Copy the frame texture back to the CPU
+    pub async fn read_frame(&self) -> Result<Image, wgpu::BufferAsyncError> {
+        self.request_frame().await
+    }
 
-        compute_pass.dispatch_workgroups(workgrp_x, workgrp_y, 1);
-        std::mem::drop(compute_pass);
+    /// Submit the copy of the frame texture now, the returned future maps it without
+    /// borrowing the renderer
+    pub fn request_frame(
+        &self,
+    ) -> impl std::future::Future<Output = Result<Image, wgpu::BufferAsyncError>> + 'static {
+        let texture = self
+            .frame_texture()
+            .expect("Renderer must be resized before reading a frame");
+        let (width, height) = (self.width, self.height);
+        let bytes_per_row = Renderer::img_bytes_per_row(width);
+
+        let staging = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Frame readback"),
+            size: bytes_per_row as u64 * height as u64,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
 
-        // Copy to surface texture
-        let texture = self.frame_texture.as_ref().unwrap();
-        encoder.copy_texture_to_texture(
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Readback Encoder"),
+            });
+        encoder.copy_texture_to_buffer(
             wgpu::TexelCopyTextureInfo {
                 texture,
                 mip_level: 0,
                 origin: wgpu::Origin3d::ZERO,
                 aspect: wgpu::TextureAspect::All,
             },
-            wgpu::TexelCopyTextureInfo {
-                texture: &output.texture,
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
-                aspect: wgpu::TextureAspect::All,
+            wgpu::TexelCopyBufferInfo {
+                buffer: &staging,
+                layout: wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(bytes_per_row),
+                    rows_per_image: Some(height),
+                },
             },
             wgpu::Extent3d {
                 width,
@@ -787,10 +993,36 @@ impl Renderer {
                 depth_or_array_layers: 1,
             },
         );
-
         self.queue.submit(iter::once(encoder.finish()));
-        output.present();
 
-        Ok(())
+        let device = self.device.clone();
+        async move {
+            let slice = staging.slice(..);
+            let (sender, receiver) = futures_channel::oneshot::channel();
+            slice.map_async(wgpu::MapMode::Read, move |res| {
+                let _ = sender.send(res);
+            });
+            // NOTE: No-op on the web, the browser resolves the mapping on its own
+            device.poll(wgpu::Maintain::Wait);
+            receiver.await.expect("Readback callback was dropped")?;
+
+            // Drop the row padding required by the copy
+            let row_len = width as usize * std::mem::size_of::<u32>();
+            let mut data = Vec::with_capacity(row_len * height as usize);
+            {
+                let mapped = slice.get_mapped_range();
+                for row in mapped.chunks(bytes_per_row as usize) {
+                    data.extend_from_slice(&row[..row_len]);
+                }
+            }
+            staging.unmap();
+
+            Ok(Image {
+                width,
+                height,
+                data,
+            })
+        }
     }
 }
+
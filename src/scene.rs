@@ -0,0 +1,579 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::animation::{AnimationDesc, CameraTrack};
+use crate::settings::RenderSettings;
+use crate::sphere::{Material, Sphere};
+
+// Scene shown when none is given
+const DEFAULT_SCENE: &str = include_str!("../scenes/default.json");
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MaterialKind {
+    #[default]
+    Lambert,
+    Metal,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct MaterialDesc {
+    pub albedo: [f32; 3],
+    #[serde(default)]
+    pub kind: MaterialKind,
+    #[serde(default)]
+    pub fuzz: f32,
+}
+
+impl From<&MaterialDesc> for Material {
+    fn from(desc: &MaterialDesc) -> Self {
+        Material {
+            albedo: [desc.albedo[0], desc.albedo[1], desc.albedo[2], 1.0],
+            kind: match desc.kind {
+                MaterialKind::Lambert => 0,
+                MaterialKind::Metal => 1,
+            },
+            fuzz: desc.fuzz,
+            ..Default::default()
+        }
+    }
+}
+
+impl From<&Material> for MaterialDesc {
+    fn from(material: &Material) -> Self {
+        let albedo = material.albedo;
+        MaterialDesc {
+            albedo: [albedo[0], albedo[1], albedo[2]],
+            kind: match material.kind {
+                0 => MaterialKind::Lambert,
+                _ => MaterialKind::Metal,
+            },
+            fuzz: material.fuzz,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct SphereDesc {
+    pub position: [f32; 3],
+    pub radius: f32,
+    // Name of an entry of `SceneDesc::materials`
+    pub material: String,
+}
+
+/// Pinhole camera, the picture size comes from the renderer
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CameraDesc {
+    pub position: [f32; 3],
+    pub look_at: [f32; 3],
+    pub up: [f32; 3],
+    // In mm, on a 24 mm high sensor
+    pub focal_length: f32,
+}
+
+impl Default for CameraDesc {
+    fn default() -> Self {
+        Self {
+            position: [0.0, 400.0, -100.0],
+            look_at: [0.0, 0.0, 500.0],
+            up: [0.0, 1.0, 0.0],
+            focal_length: 35.0,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EnvironmentDesc {
+    // Color of the rays leaving the scene
+    pub sky: [f32; 3],
+}
+
+impl Default for EnvironmentDesc {
+    fn default() -> Self {
+        Self { sky: [0.7, 0.7, 0.7] }
+    }
+}
+
+/// Scene file, JSON. Everything but `materials` and `spheres` can be left out:
+/// `{ "camera": { "position": [0, 400, -100], "look_at": [0, 0, 500], "focal_length": 35 },
+///    "environment": { "sky": [0.7, 0.7, 0.7] },
+///    "settings": { "spp": 16, "max_bounces": 100, "seed": 3 },
+///    "materials": { "chrome": { "albedo": [0.8, 0.8, 0.8], "kind": "metal" } },
+///    "spheres": [ { "position": [0, 0, 500], "radius": 50, "material": "chrome" } ] }`
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct SceneDesc {
+    #[serde(default)]
+    pub camera: CameraDesc,
+    #[serde(default)]
+    pub environment: EnvironmentDesc,
+    #[serde(default)]
+    pub settings: RenderSettings,
+    #[serde(default)]
+    pub animation: AnimationDesc,
+    pub materials: BTreeMap<String, MaterialDesc>,
+    pub spheres: Vec<SphereDesc>,
+}
+
+#[derive(Debug)]
+pub enum SceneError {
+    Io(std::io::Error),
+    Parse(serde_json::Error),
+    // The web build fetches scenes by URL
+    Fetch(String),
+    UnknownMaterial { sphere: usize, material: String },
+    // Everything `validate` found, in scene order
+    Invalid(Vec<Issue>),
+    // Edit through a handle whose material or sphere is gone
+    UnknownHandle { kind: &'static str, id: u32 },
+}
+
+/// One problem of a scene
+#[derive(Clone, Debug, PartialEq)]
+pub struct Issue {
+    // Object path of the offending value, e.g. `spheres[2].radius`
+    pub path: String,
+    pub reason: String,
+}
+
+impl std::fmt::Display for Issue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.path, self.reason)
+    }
+}
+
+impl std::fmt::Display for SceneError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SceneError::Io(e) => write!(f, "could not read scene: {e}"),
+            SceneError::Parse(e) => write!(f, "invalid scene: {e}"),
+            SceneError::Fetch(e) => write!(f, "could not fetch scene: {e}"),
+            SceneError::UnknownMaterial { sphere, material } => {
+                write!(f, "spheres[{sphere}]: unknown material '{material}'")
+            }
+            SceneError::Invalid(issues) => {
+                write!(f, "{} problem(s) in scene", issues.len())?;
+                for issue in issues {
+                    write!(f, "\n  {issue}")?;
+                }
+                Ok(())
+            }
+            SceneError::UnknownHandle { kind, id } => write!(f, "{kind} {id}: no such {kind}"),
+        }
+    }
+}
+
+impl std::error::Error for SceneError {}
+
+fn finite(v: &[f32]) -> bool {
+    v.iter().all(|x| x.is_finite())
+}
+
+// Collects issues, each check pushes when its condition fails
+#[derive(Default)]
+struct Checker {
+    issues: Vec<Issue>,
+}
+
+impl Checker {
+    fn check(&mut self, ok: bool, path: impl FnOnce() -> String, reason: impl Into<String>) {
+        if !ok {
+            self.issues.push(Issue {
+                path: path(),
+                reason: reason.into(),
+            });
+        }
+    }
+
+    fn color(&mut self, color: &[f32], path: impl FnOnce() -> String) {
+        self.check(
+            finite(color) && color.iter().all(|c| *c >= 0.0),
+            path,
+            "must be finite and not negative",
+        );
+    }
+
+    fn position(&mut self, position: &[f32], path: impl FnOnce() -> String) {
+        self.check(finite(position), path, "not a finite number");
+    }
+
+    fn radius(&mut self, radius: f32, path: impl FnOnce() -> String) {
+        self.check(
+            radius.is_finite() && radius > 0.0,
+            path,
+            "must be greater than 0",
+        );
+    }
+
+    fn fuzz(&mut self, fuzz: f32, path: impl FnOnce() -> String) {
+        self.check((0.0..=1.0).contains(&fuzz), path, "must be in [0, 1]");
+    }
+
+    fn finish(self) -> Result<(), SceneError> {
+        if self.issues.is_empty() {
+            Ok(())
+        } else {
+            Err(SceneError::Invalid(self.issues))
+        }
+    }
+}
+
+fn check_material(checker: &mut Checker, material: &Material, path: &str) {
+    let (albedo, kind, fuzz) = (material.albedo, material.kind, material.fuzz);
+    checker.color(&albedo, || format!("{path}.albedo"));
+    checker.check(
+        kind <= 1,
+        || format!("{path}.kind"),
+        format!("unknown kind {kind}, 0 is lambert and 1 metal"),
+    );
+    checker.fuzz(fuzz, || format!("{path}.fuzz"));
+}
+
+fn check_sphere(checker: &mut Checker, sphere: &Sphere, material_count: usize, path: &str) {
+    let (position, radius, material_id) = (sphere.position, sphere.radius, sphere.material_id);
+    checker.position(&position, || format!("{path}.position"));
+    checker.radius(radius, || format!("{path}.radius"));
+    checker.check(
+        usize::try_from(material_id).is_ok_and(|id| id < material_count),
+        || format!("{path}.material_id"),
+        format!("{material_id} is not one of the {material_count} materials"),
+    );
+}
+
+fn check_camera(checker: &mut Checker, camera: &CameraDesc, path: &str) {
+    checker.position(&camera.position, || format!("{path}.position"));
+    checker.position(&camera.look_at, || format!("{path}.look_at"));
+    checker.position(&camera.up, || format!("{path}.up"));
+    let forward = [0, 1, 2].map(|i| camera.look_at[i] - camera.position[i]);
+    checker.check(
+        forward != [0.0; 3],
+        || format!("{path}.look_at"),
+        "same as the camera position",
+    );
+    let up = camera.up;
+    let side = [
+        up[1] * forward[2] - up[2] * forward[1],
+        up[2] * forward[0] - up[0] * forward[2],
+        up[0] * forward[1] - up[1] * forward[0],
+    ];
+    checker.check(
+        forward == [0.0; 3] || side != [0.0; 3],
+        || format!("{path}.up"),
+        "parallel to the view direction",
+    );
+    checker.check(
+        camera.focal_length.is_finite() && camera.focal_length > 0.0,
+        || format!("{path}.focal_length"),
+        "must be greater than 0",
+    );
+}
+
+fn check_settings(checker: &mut Checker, settings: &RenderSettings) {
+    checker.check(
+        settings.spp > 0,
+        || "settings.spp".into(),
+        "must be greater than 0",
+    );
+    checker.check(
+        settings.tmin.is_finite() && settings.tmin >= 0.0,
+        || "settings.tmin".into(),
+        "must be finite and not negative",
+    );
+    checker.check(
+        settings.tmax > settings.tmin,
+        || "settings.tmax".into(),
+        "must be greater than tmin",
+    );
+    checker.check(
+        settings.bounce_tmin.is_finite() && settings.bounce_tmin >= 0.0,
+        || "settings.bounce_tmin".into(),
+        "must be finite and not negative",
+    );
+    checker.check(
+        settings.clamp.is_finite() && settings.clamp >= 0.0,
+        || "settings.clamp".into(),
+        "must be finite and not negative, 0 turns it off",
+    );
+}
+
+/// Check settings on their own, the issues are named like in a scene file
+pub fn validate_settings(settings: &RenderSettings) -> Result<(), SceneError> {
+    let mut checker = Checker::default();
+    check_settings(&mut checker, settings);
+    checker.finish()
+}
+
+fn check_animation(checker: &mut Checker, animation: &AnimationDesc) {
+    checker.check(
+        animation.fps.is_finite() && animation.fps > 0.0,
+        || "animation.fps".into(),
+        "must be greater than 0",
+    );
+    checker.check(
+        animation.duration.is_finite() && animation.duration >= 0.0,
+        || "animation.duration".into(),
+        "must be finite and not negative",
+    );
+    match &animation.camera {
+        CameraTrack::Still => {}
+        CameraTrack::Turntable { turns } => checker.check(
+            turns.is_finite(),
+            || "animation.camera.turntable.turns".into(),
+            "not a finite number",
+        ),
+        CameraTrack::Keyframes(keys) => {
+            checker.check(
+                !keys.is_empty(),
+                || "animation.camera.keyframes".into(),
+                "needs at least one key",
+            );
+            let mut previous = f32::NEG_INFINITY;
+            for (i, key) in keys.iter().enumerate() {
+                let path = format!("animation.camera.keyframes[{i}]");
+                checker.check(
+                    key.time.is_finite() && key.time > previous,
+                    || format!("{path}.time"),
+                    "must be finite and after the previous key",
+                );
+                previous = key.time;
+                check_camera(checker, &key.camera, &format!("{path}.camera"));
+            }
+        }
+    }
+}
+
+/// Check materials and spheres right before they reach the GPU, where a bad material id
+/// reads out of bounds
+pub fn validate_world(materials: &[Material], spheres: &[Sphere]) -> Result<(), SceneError> {
+    let mut checker = Checker::default();
+    for (i, material) in materials.iter().enumerate() {
+        check_material(&mut checker, material, &format!("materials[{i}]"));
+    }
+    for (i, sphere) in spheres.iter().enumerate() {
+        check_sphere(&mut checker, sphere, materials.len(), &format!("spheres[{i}]"));
+    }
+    checker.finish()
+}
+
+/// Check a camera on its own, the issues are named like in a scene file
+pub fn validate_camera(camera: &CameraDesc) -> Result<(), SceneError> {
+    let mut checker = Checker::default();
+    check_camera(&mut checker, camera, "camera");
+    checker.finish()
+}
+
+/// Check one material, `path` names it in the issues
+pub(crate) fn validate_material(material: &Material, path: &str) -> Result<(), SceneError> {
+    let mut checker = Checker::default();
+    check_material(&mut checker, material, path);
+    checker.finish()
+}
+
+/// Check one sphere against the number of materials, `path` names it in the issues
+pub(crate) fn validate_sphere(
+    sphere: &Sphere,
+    material_count: usize,
+    path: &str,
+) -> Result<(), SceneError> {
+    let mut checker = Checker::default();
+    check_sphere(&mut checker, sphere, material_count, path);
+    checker.finish()
+}
+
+impl SceneDesc {
+    pub fn from_json(json: &str) -> Result<Self, SceneError> {
+        serde_json::from_str(json).map_err(SceneError::Parse)
+    }
+
+    /// The demo scene, `scenes/default.json`
+    pub fn default_scene() -> Self {
+        Self::from_json(DEFAULT_SCENE).expect("Built-in scene")
+    }
+
+    pub fn load(path: impl AsRef<std::path::Path>) -> Result<Self, SceneError> {
+        let json = std::fs::read_to_string(path).map_err(SceneError::Io)?;
+        Self::from_json(&json)
+    }
+
+    /// Check the values serde can't, returns every problem found
+    pub fn validate(&self) -> Result<(), SceneError> {
+        let mut checker = Checker::default();
+
+        check_camera(&mut checker, &self.camera, "camera");
+        checker.color(&self.environment.sky, || "environment.sky".into());
+        check_settings(&mut checker, &self.settings);
+        check_animation(&mut checker, &self.animation);
+
+        for (name, material) in self.materials.iter() {
+            checker.color(&material.albedo, || format!("materials.{name}.albedo"));
+            checker.fuzz(material.fuzz, || format!("materials.{name}.fuzz"));
+        }
+        for (i, sphere) in self.spheres.iter().enumerate() {
+            checker.position(&sphere.position, || format!("spheres[{i}].position"));
+            checker.radius(sphere.radius, || format!("spheres[{i}].radius"));
+            checker.check(
+                self.materials.contains_key(&sphere.material),
+                || format!("spheres[{i}].material"),
+                format!("unknown material '{}'", sphere.material),
+            );
+        }
+        checker.finish()
+    }
+
+    /// A scene file of a world, the materials are named by id from `names`, e.g. the keys of
+    /// the scene file it came from, the ones past its end `material{id}`. The rest is left
+    /// to the defaults
+    pub fn from_world(materials: &[Material], spheres: &[Sphere], names: &[String]) -> Self {
+        let name = |id: usize| match names.get(id) {
+            Some(name) => name.clone(),
+            // Kept apart from `names`, a clash would merge two materials
+            None => {
+                let mut name = format!("material{id}");
+                while names.contains(&name) {
+                    name.push('_');
+                }
+                name
+            }
+        };
+        SceneDesc {
+            camera: CameraDesc::default(),
+            environment: EnvironmentDesc::default(),
+            settings: RenderSettings::default(),
+            animation: AnimationDesc::default(),
+            materials: materials
+                .iter()
+                .enumerate()
+                .map(|(id, material)| (name(id), MaterialDesc::from(material)))
+                .collect(),
+            spheres: spheres
+                .iter()
+                .map(|sphere| SphereDesc {
+                    position: sphere.position,
+                    radius: sphere.radius,
+                    material: name(sphere.material_id as usize),
+                })
+                .collect(),
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("Scene serializes to JSON")
+    }
+
+    /// Materials and spheres as uploaded to the GPU
+    pub fn to_world(&self) -> Result<(Vec<Material>, Vec<Sphere>), SceneError> {
+        let names: Vec<&String> = self.materials.keys().collect();
+        let materials = self
+            .materials
+            .values()
+            .map(Material::from)
+            .collect();
+
+        let spheres = self
+            .spheres
+            .iter()
+            .enumerate()
+            .map(|(i, desc)| {
+                let material_id = names
+                    .iter()
+                    .position(|name| **name == desc.material)
+                    .ok_or_else(|| SceneError::UnknownMaterial {
+                        sphere: i,
+                        material: desc.material.clone(),
+                    })?;
+                Ok(Sphere::new(desc.position, material_id as i32, desc.radius))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok((materials, spheres))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validate_reports_every_issue() {
+        let scene = SceneDesc::from_json(
+            r#"{
+                "camera": { "up": [0, 0, 1], "position": [0, 0, 0], "look_at": [0, 0, 5] },
+                "materials": { "red": { "albedo": [1, 0, 0], "fuzz": 2 } },
+                "spheres": [
+                    { "position": [0, 0, 5], "radius": -1, "material": "red" },
+                    { "position": [0, 0, 5], "radius": 1, "material": "blue" }
+                ]
+            }"#,
+        )
+        .unwrap();
+        let Err(SceneError::Invalid(issues)) = scene.validate() else {
+            panic!("scene should be invalid");
+        };
+        let paths: Vec<&str> = issues.iter().map(|issue| issue.path.as_str()).collect();
+        assert_eq!(
+            paths,
+            [
+                "camera.up",
+                "materials.red.fuzz",
+                "spheres[0].radius",
+                "spheres[1].material"
+            ]
+        );
+        assert!(SceneDesc::default_scene().validate().is_ok());
+    }
+
+    #[test]
+    fn validate_world_catches_bad_material_ids() {
+        let materials = [Material::default()];
+        let spheres = [
+            Sphere::new([0.0; 3], 0, 1.0),
+            Sphere::new([f32::NAN, 0.0, 0.0], 1, 1.0),
+            Sphere::new([0.0; 3], -1, 1.0),
+        ];
+        let Err(SceneError::Invalid(issues)) = validate_world(&materials, &spheres) else {
+            panic!("world should be invalid");
+        };
+        let paths: Vec<&str> = issues.iter().map(|issue| issue.path.as_str()).collect();
+        assert_eq!(
+            paths,
+            [
+                "spheres[1].position",
+                "spheres[1].material_id",
+                "spheres[2].material_id"
+            ]
+        );
+    }
+
+    #[test]
+    fn from_world_round_trips_through_json() {
+        let (materials, spheres) = SceneDesc::default_scene().to_world().unwrap();
+        let json = SceneDesc::from_world(&materials, &spheres, &[]).to_json();
+        let (materials2, spheres2) = SceneDesc::from_json(&json).unwrap().to_world().unwrap();
+        assert_eq!(spheres.len(), spheres2.len());
+        for (a, b) in spheres.iter().zip(&spheres2) {
+            assert_eq!({ a.position }, { b.position });
+            assert_eq!({ a.radius }, { b.radius });
+            // Ids may be renumbered, the materials stay the same
+            let (ma, mb) = (materials[a.material_id as usize], materials2[b.material_id as usize]);
+            assert_eq!((ma.albedo, ma.kind, ma.fuzz), (mb.albedo, mb.kind, mb.fuzz));
+        }
+    }
+
+    #[test]
+    fn from_world_keeps_material_names() {
+        let scene = SceneDesc::default_scene();
+        let (materials, spheres) = scene.to_world().unwrap();
+        let names: Vec<String> = scene.materials.keys().cloned().collect();
+        let desc = SceneDesc::from_world(&materials, &spheres, &names);
+        assert!(desc.materials.keys().eq(scene.materials.keys()));
+        assert!(desc
+            .spheres
+            .iter()
+            .map(|sphere| &sphere.material)
+            .eq(scene.spheres.iter().map(|sphere| &sphere.material)));
+    }
+}
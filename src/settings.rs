@@ -0,0 +1,155 @@
+use crate::scene::EnvironmentDesc;
+
+/// What the shade pass writes instead of the shaded color, to look into a scene
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DebugView {
+    #[default]
+    Off,
+    // Normal of the primary hit, mapped to [0, 1]
+    Normal,
+    // Albedo of the material of the primary hit
+    Albedo,
+    // Bounces of the path over `max_bounces`, white when the path ran out of bounces
+    Bounces,
+}
+
+/// Sampling and tracing parameters of a render, they reach the shaders through
+/// `FrameUniform` every frame so changing them never recompiles a pipeline, but for
+/// `deterministic` and `max_bounces` with `Specialization::fixed_bounces`
+#[derive(Copy, Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RenderSettings {
+    // Samples per pixel of an offscreen render, the viewer keeps accumulating
+    pub spp: u32,
+    pub max_bounces: u32,
+    pub seed: u32,
+    // Stop at `spp` samples and draw random numbers from an integer hash of the pixel, the
+    // sample and the seed, the same scene gives the same image on the same adapter
+    pub deterministic: bool,
+    // Position on the timeline of the scene animation, see `AnimationDesc::frame_settings`.
+    // The frame is mixed into the seed so the noise doesn't stay put on screen
+    pub frame: u32,
+    // In seconds, poses the camera
+    pub time: f32,
+    // Closest and farthest hit of a camera ray
+    pub tmin: f32,
+    pub tmax: f32,
+    // Closest hit of a bounced ray, keeps it from hitting the surface it leaves
+    pub bounce_tmin: f32,
+    // Largest value of a color channel of a sample, trades fireflies for some energy.
+    // 0 turns it off
+    pub clamp: f32,
+    pub debug: DebugView,
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        Self {
+            spp: 16,
+            max_bounces: 100,
+            seed: 3,
+            deterministic: false,
+            frame: 0,
+            time: 0.0,
+            tmin: 0.001,
+            tmax: 99999.0,
+            bounce_tmin: 0.01,
+            clamp: 0.0,
+            debug: DebugView::Off,
+        }
+    }
+}
+
+impl RenderSettings {
+    /// Seed of the random numbers of the current frame
+    pub fn frame_seed(&self) -> u32 {
+        self.seed.wrapping_add(self.frame)
+    }
+}
+
+/// Compile time variants of the compute passes, set through WGSL `override` constants.
+/// Each combination builds its pipelines the first time it is used and keeps them, switching
+/// back is free
+#[derive(Copy, Clone, Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Specialization {
+    // Threads of a workgroup along x and y, every pass dispatches one thread per pixel
+    pub workgroup_size: [u32; 2],
+    // Bake `RenderSettings::max_bounces` into the shade pass, so the bounce loop has a
+    // constant bound. Every depth used builds its own pipeline
+    pub fixed_bounces: bool,
+}
+
+impl Default for Specialization {
+    fn default() -> Self {
+        Self {
+            workgroup_size: [8, 8],
+            fixed_bounces: false,
+        }
+    }
+}
+
+/// Per frame uniform of the intersect and shade passes, `RenderSettings` and the sky
+#[repr(C, packed)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct FrameUniform {
+    pub seed: f32,
+    // Index of the sample being accumulated
+    pub sample: u32,
+    pub max_bounces: u32,
+    // `seed` without the float rounding, for the deterministic mode
+    pub int_seed: u32,
+    pub sky: [f32; 3],
+    pub spp: u32,
+    pub tmin: f32,
+    pub tmax: f32,
+    pub bounce_tmin: f32,
+    pub clamp: f32,
+    // `DebugView` as u32, 0 is off
+    pub debug: u32,
+    // Arrays in uniforms need a 16 byte stride
+    _pad0: u32,
+    _pad1: u32,
+    _pad2: u32,
+}
+
+const _: () = assert!(std::mem::size_of::<FrameUniform>().is_multiple_of(16));
+
+crate::wgsl_struct!(FrameUniform as "Frame" {
+    seed: "f32",
+    sample: "u32",
+    max_bounces: "u32",
+    int_seed: "u32",
+    sky: "vec3<f32>",
+    spp: "u32",
+    tmin: "f32",
+    tmax: "f32",
+    bounce_tmin: "f32",
+    clamp: "f32",
+    debug: "u32",
+    _pad0: "u32",
+    _pad1: "u32",
+    _pad2: "u32",
+});
+
+impl FrameUniform {
+    pub fn new(settings: &RenderSettings, environment: &EnvironmentDesc, sample: u32) -> Self {
+        Self {
+            seed: settings.frame_seed() as f32,
+            sample,
+            max_bounces: settings.max_bounces,
+            int_seed: settings.frame_seed(),
+            sky: environment.sky,
+            spp: settings.spp,
+            tmin: settings.tmin,
+            tmax: settings.tmax,
+            bounce_tmin: settings.bounce_tmin,
+            clamp: settings.clamp,
+            debug: settings.debug as u32,
+            _pad0: 0,
+            _pad1: 0,
+            _pad2: 0,
+        }
+    }
+}
@@ -0,0 +1,226 @@
+use std::collections::{HashMap, HashSet};
+
+/// Shaders baked into the binary, keyed on their path relative to `www/public/shaders`.
+/// Struct declarations shared with Rust are generated under `gen/`, see `wgsl::modules`
+const BUILTIN: &[(&str, &str)] = &[
+    ("rays.wgsl", include_str!("../www/public/shaders/rays.wgsl")),
+    ("intersect.wgsl", include_str!("../www/public/shaders/intersect.wgsl")),
+    ("shade.wgsl", include_str!("../www/public/shaders/shade.wgsl")),
+    ("blit.wgsl", include_str!("../www/public/shaders/blit.wgsl")),
+    ("common/sphere.wgsl", include_str!("../www/public/shaders/common/sphere.wgsl")),
+    ("common/hit.wgsl", include_str!("../www/public/shaders/common/hit.wgsl")),
+    ("common/random.wgsl", include_str!("../www/public/shaders/common/random.wgsl")),
+];
+
+#[derive(Debug)]
+pub enum ShaderError {
+    UnknownModule {
+        module: String,
+        // Module holding the #import, None for the entry point
+        from: Option<String>,
+    },
+    ImportCycle(Vec<String>),
+    Directive {
+        module: String,
+        line: usize,
+        reason: &'static str,
+    },
+}
+
+impl std::fmt::Display for ShaderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ShaderError::UnknownModule { module, from: None } => {
+                write!(f, "unknown shader module '{module}'")
+            }
+            ShaderError::UnknownModule {
+                module,
+                from: Some(from),
+            } => write!(f, "{from}: unknown shader module '{module}'"),
+            ShaderError::ImportCycle(stack) => write!(f, "import cycle: {}", stack.join(" -> ")),
+            ShaderError::Directive {
+                module,
+                line,
+                reason,
+            } => write!(f, "{module}:{line}: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for ShaderError {}
+
+/// Paths of the shaders baked into the binary, relative to `www/public/shaders`
+#[cfg_attr(
+    not(all(debug_assertions, any(feature = "web", feature = "native"))),
+    allow(dead_code)
+)]
+pub fn builtin_files() -> impl Iterator<Item = &'static str> {
+    BUILTIN.iter().map(|(name, _)| *name)
+}
+
+/// A composed shader and where each of its lines comes from
+pub struct Composed {
+    pub source: String,
+    // Module and line number of every line of `source`
+    #[cfg_attr(not(any(feature = "web", feature = "native")), allow(dead_code))]
+    origins: Vec<(String, usize)>,
+}
+
+impl Composed {
+    /// Module and line number of `line` of the composed source, 1 based both
+    #[cfg_attr(not(any(feature = "web", feature = "native")), allow(dead_code))]
+    pub fn origin(&self, line: usize) -> Option<(&str, usize)> {
+        let (module, line) = self.origins.get(line.checked_sub(1)?)?;
+        Some((module, *line))
+    }
+}
+
+/// Assembles WGSL from modules with a small preprocessor:
+/// - `#import "path.wgsl"` pastes a module once per composed shader
+/// - `#define NAME` and the defines passed to `compose`
+/// - `#ifdef NAME`, `#ifndef NAME`, `#else`, `#endif`
+#[derive(Clone)]
+pub struct Composer {
+    modules: HashMap<String, String>,
+}
+
+struct Context<'a> {
+    defines: HashSet<&'a str>,
+    imported: HashSet<String>,
+    stack: Vec<String>,
+    out: String,
+    origins: Vec<(String, usize)>,
+}
+
+impl Composer {
+    pub fn builtin() -> Self {
+        Self {
+            modules: BUILTIN
+                .iter()
+                .map(|(name, src)| (name.to_string(), src.to_string()))
+                .chain(
+                    crate::wgsl::modules()
+                        .into_iter()
+                        .map(|(name, src)| (name.to_string(), src)),
+                )
+                .collect(),
+        }
+    }
+
+    /// Add a module or replace its source
+    #[cfg_attr(not(any(feature = "web", feature = "native")), allow(dead_code))]
+    pub fn set_module(&mut self, name: &str, source: String) {
+        self.modules.insert(name.to_string(), source);
+    }
+
+    #[cfg_attr(
+        not(all(debug_assertions, any(feature = "web", feature = "native"))),
+        allow(dead_code)
+    )]
+    pub fn module(&self, name: &str) -> Option<&str> {
+        self.modules.get(name).map(String::as_str)
+    }
+
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn compose(&self, entry: &str, defines: &[&str]) -> Result<String, ShaderError> {
+        self.compose_mapped(entry, defines).map(|composed| composed.source)
+    }
+
+    /// Like `compose`, keeping track of the module lines the shader is made of
+    pub fn compose_mapped(&self, entry: &str, defines: &[&str]) -> Result<Composed, ShaderError> {
+        let mut ctx = Context {
+            defines: defines.iter().copied().collect(),
+            imported: HashSet::new(),
+            stack: Vec::new(),
+            out: String::new(),
+            origins: Vec::new(),
+        };
+        if !self.modules.contains_key(entry) {
+            return Err(ShaderError::UnknownModule {
+                module: entry.to_string(),
+                from: None,
+            });
+        }
+        self.expand(entry, &mut ctx)?;
+        Ok(Composed {
+            source: ctx.out,
+            origins: ctx.origins,
+        })
+    }
+
+    fn expand<'a>(&'a self, name: &str, ctx: &mut Context<'a>) -> Result<(), ShaderError> {
+        if ctx.stack.iter().any(|m| m == name) {
+            let mut cycle = ctx.stack.clone();
+            cycle.push(name.to_string());
+            return Err(ShaderError::ImportCycle(cycle));
+        }
+        if !ctx.imported.insert(name.to_string()) {
+            return Ok(());
+        }
+        let source = &self.modules[name];
+        ctx.stack.push(name.to_string());
+
+        // One entry per open #if, true when its lines are kept
+        let mut conds: Vec<bool> = Vec::new();
+        let directive = |line: usize, reason| ShaderError::Directive {
+            module: name.to_string(),
+            line,
+            reason,
+        };
+
+        for (i, line) in source.lines().enumerate() {
+            let line_no = i + 1;
+            let active = conds.iter().all(|c| *c);
+            let trimmed = line.trim();
+            if !trimmed.starts_with('#') {
+                if active {
+                    ctx.out.push_str(line);
+                    ctx.out.push('\n');
+                    ctx.origins.push((name.to_string(), line_no));
+                }
+                continue;
+            }
+
+            let mut words = trimmed[1..].split_whitespace();
+            let keyword = words.next().unwrap_or("");
+            let arg = words.next();
+            match (keyword, arg) {
+                ("import" | "include", Some(path)) => {
+                    if !active {
+                        continue;
+                    }
+                    let module = path.trim_matches('"');
+                    if !self.modules.contains_key(module) {
+                        return Err(ShaderError::UnknownModule {
+                            module: module.to_string(),
+                            from: Some(name.to_string()),
+                        });
+                    }
+                    self.expand(module, ctx)?;
+                }
+                ("define", Some(def)) => {
+                    if active {
+                        ctx.defines.insert(def);
+                    }
+                }
+                ("ifdef", Some(def)) => conds.push(ctx.defines.contains(def)),
+                ("ifndef", Some(def)) => conds.push(!ctx.defines.contains(def)),
+                ("else", None) => match conds.last_mut() {
+                    Some(cond) => *cond = !*cond,
+                    None => return Err(directive(line_no, "#else without #ifdef")),
+                },
+                ("endif", None) => {
+                    if conds.pop().is_none() {
+                        return Err(directive(line_no, "#endif without #ifdef"));
+                    }
+                }
+                _ => return Err(directive(line_no, "unknown or malformed directive")),
+            }
+        }
+        if !conds.is_empty() {
+            return Err(directive(source.lines().count(), "missing #endif"));
+        }
+        ctx.stack.pop();
+        Ok(())
+    }
+}
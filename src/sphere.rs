@@ -18,8 +18,22 @@ pub struct Material {
     pub _pad0: [u32; 2],
 }
 
-const _: () = assert!(std::mem::size_of::<Material>() % 16 == 0);
-const _: () = assert!(std::mem::size_of::<Sphere>() % 16 == 0);
+const _: () = assert!(std::mem::size_of::<Material>().is_multiple_of(16));
+const _: () = assert!(std::mem::size_of::<Sphere>().is_multiple_of(16));
+
+crate::wgsl_struct!(Sphere as "Sphere" {
+    position: "vec3<f32>",
+    radius: "f32",
+    material_id: "i32",
+    _pad0: "array<u32, 3>",
+});
+
+crate::wgsl_struct!(Material as "Material" {
+    albedo: "vec4<f32>",
+    kind: "u32",
+    fuzz: "f32",
+    _pad0: "array<u32, 2>",
+});
 
 impl Default for Material {
     fn default() -> Self {
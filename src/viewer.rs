@@ -0,0 +1,481 @@
+use std::{iter, sync::Arc};
+
+use web_time::{Duration, Instant};
+use winit::{event::WindowEvent, window::Window};
+
+use crate::controls::OrbitControls;
+use crate::graph::{Access, RenderGraph, RenderPassDesc, Slot};
+use crate::renderer::{Renderer, ShaderUpdate};
+use crate::scene::{SceneDesc, SceneError};
+
+/// Smallest and largest render scale, see `Viewer::set_render_scale`
+pub const RENDER_SCALE_RANGE: std::ops::RangeInclusive<f32> = 0.25..=2.0;
+
+/// When the viewer draws new samples, it stops requesting frames otherwise
+#[derive(Copy, Clone, Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RenderPolicy {
+    // A sample every frame, forever
+    Continuous,
+    // Until the frame holds this many samples, any change starts over
+    Converge(u32),
+    // One sample per change or `Viewer::request_frame`
+    OnDemand,
+}
+
+impl Default for RenderPolicy {
+    fn default() -> Self {
+        RenderPolicy::Converge(1024)
+    }
+}
+
+/// Resolution drop while the camera is dragged, one sample per frame at `scale` times the
+/// render scale until the input stops for `settle`
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct DynamicResolution {
+    pub scale: f32,
+    pub settle: Duration,
+}
+
+impl Default for DynamicResolution {
+    fn default() -> Self {
+        Self {
+            scale: 0.5,
+            settle: Duration::from_millis(250),
+        }
+    }
+}
+
+/// Draws the frame texture with a render pass, for surfaces without `Renderer::FRAME_FORMAT`
+/// or of another size than the frame
+struct Blit {
+    graph: RenderGraph,
+}
+
+impl Blit {
+    const FRAME: &'static str = "frame";
+    const SAMPLER: &'static str = "sampler";
+    const SURFACE: &'static str = "surface";
+
+    fn new(renderer: &Renderer, format: wgpu::TextureFormat) -> Self {
+        let device = renderer.device();
+        let mut graph = RenderGraph::new();
+        graph.add_import(Blit::FRAME);
+        graph.add_import(Blit::SAMPLER);
+        graph.add_target(Blit::SURFACE, format);
+        graph.add_render_pass(RenderPassDesc {
+            label: "Blit pass",
+            shader: "blit.wgsl",
+            defines: vec![],
+            vertex_entry: "vs_main",
+            fragment_entry: "fs_main",
+            target: Blit::SURFACE,
+            groups: vec![vec![
+                Slot::new(0, Blit::FRAME, Access::Sampled),
+                Slot::new(1, Blit::SAMPLER, Access::Sampler),
+            ]],
+        });
+        graph
+            .compile(device, renderer.shaders())
+            .expect("Builtin blit shader");
+        // Smooths the upscale of a frame rendered below the surface resolution
+        graph.import_sampler(
+            Blit::SAMPLER,
+            device.create_sampler(&wgpu::SamplerDescriptor {
+                label: Some("Blit sampler"),
+                mag_filter: wgpu::FilterMode::Linear,
+                min_filter: wgpu::FilterMode::Linear,
+                ..Default::default()
+            }),
+        );
+        Self { graph }
+    }
+
+    fn draw(
+        &mut self,
+        renderer: &Renderer,
+        encoder: &mut wgpu::CommandEncoder,
+        target: &wgpu::TextureView,
+    ) {
+        // Only a new frame texture drops the bind group
+        self.graph
+            .import_view(Blit::FRAME, renderer.frame_view().unwrap().clone());
+        self.graph.import_target(Blit::SURFACE, target.clone());
+        self.graph
+            .execute(renderer.device(), encoder)
+            .expect("Blit resources are imported");
+    }
+}
+
+/// Why a screenshot could not be taken
+#[derive(Debug)]
+pub enum ScreenshotError {
+    // Before the first resize
+    NoFrame,
+    Readback(wgpu::BufferAsyncError),
+    Encode(png::EncodingError),
+}
+
+impl std::fmt::Display for ScreenshotError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NoFrame => write!(f, "nothing rendered yet"),
+            Self::Readback(e) => write!(f, "could not read the frame: {e}"),
+            Self::Encode(e) => write!(f, "could not encode the PNG: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for ScreenshotError {}
+
+/// Presents the frames of a `Renderer` on a window surface
+pub struct Viewer {
+    pub renderer: Renderer,
+    surface: wgpu::Surface<'static>,
+    config: wgpu::SurfaceConfiguration,
+    blit: Blit,
+    // Whether the surface takes the frame texture as is, when the sizes match
+    copy_compatible: bool,
+    pub window: Arc<Window>,
+    // Of the surface, in physical pixels
+    pub size: winit::dpi::PhysicalSize<u32>,
+    // Frame size relative to the surface size
+    render_scale: f32,
+    dynamic_resolution: DynamicResolution,
+    controls: OrbitControls,
+    // Last camera input, None once the frame is back to full resolution
+    last_input: Option<Instant>,
+    // No samples are rendered while paused, the last frame stays on screen
+    pub paused: bool,
+    // False while the window is occluded, or on the web the page hidden or the canvas
+    // scrolled out of view
+    pub visible: bool,
+    render_policy: RenderPolicy,
+    // A sample asked for with `RenderPolicy::OnDemand`
+    frame_requested: bool,
+    // Resolved by the first frame that doesn't want more samples, see `on_converged`
+    converged: Vec<futures_channel::oneshot::Sender<()>>,
+}
+
+impl Viewer {
+    pub async fn new(window: Window) -> Self {
+        let window = Arc::new(window);
+        let size = window.inner_size();
+        #[cfg(target_arch = "wasm32")]
+        let backends = wgpu::Backends::BROWSER_WEBGPU;
+        // WGPU_BACKEND can narrow this down
+        #[cfg(not(target_arch = "wasm32"))]
+        let backends = wgpu::Backends::from_env()
+            .unwrap_or(wgpu::Backends::VULKAN | wgpu::Backends::GL);
+        let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor {
+            backends,
+            // Validation in debug builds only, WGPU_VALIDATION and WGPU_DEBUG override it
+            flags: wgpu::InstanceFlags::from_build_config().with_env(),
+            ..Default::default()
+        });
+
+        let surface = instance.create_surface(Arc::clone(&window)).unwrap();
+
+        let adapter = instance
+            .request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: wgpu::PowerPreference::default(),
+                compatible_surface: Some(&surface),
+                force_fallback_adapter: false,
+            })
+            .await
+            .unwrap();
+
+        let (device, queue) = Renderer::request_device(&adapter).await.unwrap();
+
+        let surface_caps = surface.get_capabilities(&adapter);
+
+        // NOTE: The frame texture is copied as is when the surface shares its format,
+        // otherwise it is drawn through a non sRGB view so the values stay untouched
+        let format = if surface_caps.formats.contains(&Renderer::FRAME_FORMAT) {
+            Renderer::FRAME_FORMAT
+        } else {
+            surface_caps.formats[0]
+        };
+        let view_format = format.remove_srgb_suffix();
+        let usage = if format == Renderer::FRAME_FORMAT {
+            wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_DST
+        } else {
+            wgpu::TextureUsages::RENDER_ATTACHMENT
+        };
+        let config = wgpu::SurfaceConfiguration {
+            usage,
+            format,
+            width: size.width.max(1),
+            height: size.height.max(1),
+            present_mode: surface_caps.present_modes[0],
+            alpha_mode: surface_caps.alpha_modes[0],
+            desired_maximum_frame_latency: 2,
+            view_formats: if view_format == format { vec![] } else { vec![view_format] },
+        };
+
+        let renderer = Renderer::new(device, queue);
+        let copy_compatible = format == Renderer::FRAME_FORMAT;
+        if !copy_compatible {
+            log::info!("Surface format {format:?}, presenting through a blit");
+        }
+        let blit = Blit::new(&renderer, view_format);
+
+        Self {
+            renderer,
+            surface,
+            config,
+            blit,
+            copy_compatible,
+            window,
+            size,
+            render_scale: 1.0,
+            dynamic_resolution: DynamicResolution::default(),
+            controls: OrbitControls::default(),
+            last_input: None,
+            paused: false,
+            visible: true,
+            render_policy: RenderPolicy::default(),
+            frame_requested: false,
+            converged: Vec::new(),
+        }
+    }
+
+    pub fn window(&self) -> &Window {
+        &self.window
+    }
+
+    pub fn make_world(&mut self) {
+        self.renderer.make_world();
+    }
+
+    pub fn load_scene(&mut self, scene: &SceneDesc) -> Result<(), SceneError> {
+        self.renderer.load_scene(scene)
+    }
+
+    /// The current frame as a PNG, with the scene and camera in a tEXt chunk.
+    /// The readback is submitted right away, later frames don't end up in the image
+    pub fn screenshot_png(
+        &self,
+    ) -> impl std::future::Future<Output = Result<Vec<u8>, ScreenshotError>> + 'static {
+        let frame = self
+            .renderer
+            .frame_texture()
+            .map(|_| (self.renderer.request_frame(), self.renderer.scene_desc()));
+        async move {
+            let (frame, scene) = frame.ok_or(ScreenshotError::NoFrame)?;
+            let image = frame.await.map_err(ScreenshotError::Readback)?;
+            image.to_png(Some(&scene)).map_err(ScreenshotError::Encode)
+        }
+    }
+
+    pub fn on_resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) -> bool {
+        if new_size.width > 0 && new_size.height > 0 {
+            self.size = new_size;
+            self.config.width = new_size.width;
+            self.config.height = new_size.height;
+            self.surface.configure(self.renderer.device(), &self.config);
+            return self.resize_frame();
+        }
+        false
+    }
+
+    #[cfg_attr(not(target_arch = "wasm32"), allow(dead_code))]
+    pub fn render_scale(&self) -> f32 {
+        self.render_scale
+    }
+
+    /// Render frames at `scale` times the surface size, clamped to `RENDER_SCALE_RANGE`.
+    /// Below 1 trades sharpness for speed, above 1 supersamples
+    pub fn set_render_scale(&mut self, scale: f32) -> bool {
+        let scale = scale.clamp(*RENDER_SCALE_RANGE.start(), *RENDER_SCALE_RANGE.end());
+        if scale == self.render_scale {
+            return true;
+        }
+        self.render_scale = scale;
+        self.resize_frame()
+    }
+
+    #[cfg_attr(not(target_arch = "wasm32"), allow(dead_code))]
+    pub fn set_dynamic_resolution(&mut self, dynamic: DynamicResolution) {
+        self.dynamic_resolution = DynamicResolution {
+            scale: dynamic.scale.clamp(0.01, 1.0),
+            ..dynamic
+        };
+        if self.last_input.is_some() {
+            self.resize_frame();
+        }
+    }
+
+    #[cfg_attr(not(target_arch = "wasm32"), allow(dead_code))]
+    pub fn render_policy(&self) -> RenderPolicy {
+        self.render_policy
+    }
+
+    #[cfg_attr(not(target_arch = "wasm32"), allow(dead_code))]
+    pub fn set_render_policy(&mut self, policy: RenderPolicy) {
+        self.render_policy = policy;
+    }
+
+    /// Draw one more sample, for `RenderPolicy::OnDemand`
+    #[cfg_attr(not(target_arch = "wasm32"), allow(dead_code))]
+    pub fn request_frame(&mut self) {
+        self.frame_requested = true;
+    }
+
+    /// `Renderer::rebuild_shaders` of the renderer and the blit
+    pub(crate) fn rebuild_shaders(
+        &self,
+        modules: Vec<(String, String)>,
+    ) -> impl std::future::Future<Output = Result<ShaderUpdate, String>> + 'static {
+        self.renderer.rebuild_shaders(modules, &[&self.blit.graph])
+    }
+
+    /// `Renderer::install_shaders` of the renderer and the blit
+    pub(crate) fn install_shaders(&mut self, update: ShaderUpdate) {
+        for rebuild in self.renderer.install_shaders(update) {
+            self.blit.graph.install(rebuild);
+        }
+    }
+
+    /// Resolves once a drawn frame holds every sample `wants_frame` asks for. Frames are
+    /// only drawn while the viewer is visible and not paused, so it waits meanwhile
+    #[cfg_attr(not(target_arch = "wasm32"), allow(dead_code))]
+    pub fn on_converged(&mut self) -> futures_channel::oneshot::Receiver<()> {
+        let (sender, receiver) = futures_channel::oneshot::channel();
+        self.converged.push(sender);
+        receiver
+    }
+
+    /// Whether the next frame would add a sample, the app only asks for frames then
+    pub fn wants_frame(&self) -> bool {
+        if self.paused || !self.visible {
+            return false;
+        }
+        // The scene was edited, the camera moved or the settings changed
+        let changed = self.renderer.sample_count() == 0 || self.renderer.scene().is_dirty();
+        // Settling needs frames to notice the end of the input
+        if changed || self.last_input.is_some() {
+            return true;
+        }
+        let settings = self.renderer.settings();
+        // The deterministic mode stops at `spp` on its own
+        if settings.deterministic && self.renderer.sample_count() >= settings.spp {
+            return false;
+        }
+        match self.render_policy {
+            RenderPolicy::Continuous => true,
+            RenderPolicy::Converge(samples) => self.renderer.sample_count() < samples,
+            RenderPolicy::OnDemand => self.frame_requested,
+        }
+    }
+
+    /// Move the camera with the mouse, returns true if the event was used
+    pub fn input(&mut self, event: &WindowEvent) -> bool {
+        let Some(view) = self
+            .controls
+            .handle(event, self.renderer.view(), self.size.height)
+        else {
+            return false;
+        };
+        self.renderer.set_view(view);
+        self.interact();
+        true
+    }
+
+    /// Drop to the dynamic resolution until the input stops
+    pub fn interact(&mut self) {
+        let started = self.last_input.replace(Instant::now()).is_none();
+        if started {
+            self.resize_frame();
+        }
+    }
+
+    // Back to full resolution once the input settled, returns true while interacting
+    fn settle(&mut self) -> bool {
+        let Some(last_input) = self.last_input else {
+            return false;
+        };
+        if last_input.elapsed() < self.dynamic_resolution.settle {
+            return true;
+        }
+        self.last_input = None;
+        self.resize_frame();
+        false
+    }
+
+    fn resize_frame(&mut self) -> bool {
+        if self.size.width == 0 || self.size.height == 0 {
+            return false;
+        }
+        let scale = match self.last_input {
+            Some(_) => self.render_scale * self.dynamic_resolution.scale,
+            None => self.render_scale,
+        };
+        let scaled = |v: u32| ((v as f32 * scale).round() as u32).max(1);
+        self.renderer
+            .resize(scaled(self.size.width), scaled(self.size.height))
+    }
+
+    /// Present the frame, with one more sample if `wants_frame`
+    pub fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
+        let sample = self.wants_frame();
+        // A single sample per frame while moving
+        if self.settle() {
+            self.renderer.reset_accumulation();
+        }
+        let output = self.surface.get_current_texture()?;
+
+        let mut encoder =
+            self.renderer
+                .device()
+                .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                    label: Some("Render Encoder"),
+                });
+
+        if sample {
+            self.renderer.encode(&mut encoder);
+            self.frame_requested = false;
+        }
+
+        let (width, height) = self.renderer.size();
+        if !self.copy_compatible || (width, height) != (self.config.width, self.config.height) {
+            let view = output.texture.create_view(&wgpu::TextureViewDescriptor {
+                format: Some(self.config.format.remove_srgb_suffix()),
+                ..Default::default()
+            });
+            self.blit.draw(&self.renderer, &mut encoder, &view);
+        } else {
+            // Copy to surface texture
+            let texture = self.renderer.frame_texture().unwrap();
+            encoder.copy_texture_to_texture(
+                wgpu::TexelCopyTextureInfo {
+                    texture,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d::ZERO,
+                    aspect: wgpu::TextureAspect::All,
+                },
+                wgpu::TexelCopyTextureInfo {
+                    texture: &output.texture,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d::ZERO,
+                    aspect: wgpu::TextureAspect::All,
+                },
+                wgpu::Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: 1,
+                },
+            );
+        }
+
+        self.renderer.queue().submit(iter::once(encoder.finish()));
+        output.present();
+
+        if self.visible && !self.paused && !self.wants_frame() {
+            for sender in self.converged.drain(..) {
+                let _ = sender.send(());
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,148 @@
+use wasm_bindgen::prelude::*;
+
+/// GET `url` as text
+pub async fn fetch_text(url: &str) -> Result<String, JsValue> {
+    fetch(url, web_sys::RequestCache::Default).await
+}
+
+/// GET `url` as text, skipping the HTTP cache, for files that change while the page is open
+#[cfg_attr(not(debug_assertions), allow(dead_code))]
+pub async fn fetch_text_uncached(url: &str) -> Result<String, JsValue> {
+    fetch(url, web_sys::RequestCache::NoStore).await
+}
+
+async fn fetch(url: &str, cache: web_sys::RequestCache) -> Result<String, JsValue> {
+    use wasm_bindgen_futures::JsFuture;
+    use web_sys::{Request, RequestInit, RequestMode, Response};
+
+    let opts = RequestInit::new();
+    opts.set_method("GET");
+    opts.set_mode(RequestMode::Cors);
+    opts.set_cache(cache);
+
+    let request = Request::new_with_str_and_init(url, &opts)?;
+
+    let window = web_sys::window().expect("No web window");
+    let resp_value = JsFuture::from(window.fetch_with_request(&request)).await?;
+    let resp: Response = resp_value.dyn_into()?;
+    if !resp.ok() {
+        return Err(JsValue::from_str(&format!("HTTP {}", resp.status())));
+    }
+
+    let text = JsFuture::from(resp.text()?).await?;
+    Ok(text.as_string().unwrap())
+}
+
+/// Save the PNG `bytes` as `name` through a temporary link, like a download link the user clicked
+pub fn download_png(name: &str, bytes: &[u8]) -> Result<(), JsValue> {
+    let document = web_sys::window()
+        .and_then(|w| w.document())
+        .expect("No document");
+    let parts = js_sys::Array::of1(&js_sys::Uint8Array::from(bytes));
+    let options = web_sys::BlobPropertyBag::new();
+    options.set_type("image/png");
+    let blob = web_sys::Blob::new_with_u8_array_sequence_and_options(&parts, &options)?;
+    let url = web_sys::Url::create_object_url_with_blob(&blob)?;
+
+    let link: web_sys::HtmlAnchorElement = document.create_element("a")?.dyn_into()?;
+    link.set_href(&url);
+    link.set_download(name);
+    link.click();
+    web_sys::Url::revoke_object_url(&url)
+}
+
+/// Resolves after `ms` milliseconds
+pub async fn sleep(ms: i32) {
+    let timeout = js_sys::Promise::new(&mut |resolve, _| {
+        web_sys::window()
+            .expect("No web window")
+            .set_timeout_with_callback_and_timeout_and_arguments_0(&resolve, ms)
+            .expect("setTimeout");
+    });
+    let _ = wasm_bindgen_futures::JsFuture::from(timeout).await;
+}
+
+/// Records a canvas frame by frame into a WebM video with MediaRecorder.
+/// The recorder is paused between frames, each frame lasts 1 / fps of the video however long
+/// it took to render
+pub struct CanvasRecorder {
+    recorder: web_sys::MediaRecorder,
+    track: web_sys::CanvasCaptureMediaStreamTrack,
+    // Blobs handed over by `dataavailable`, the video once `finish` stopped the recorder
+    chunks: js_sys::Array,
+    _on_data: Closure<dyn FnMut(web_sys::BlobEvent)>,
+    frame_ms: i32,
+}
+
+impl CanvasRecorder {
+    const MIME_TYPE: &'static str = "video/webm";
+
+    pub fn new(canvas: &web_sys::HtmlCanvasElement, fps: f32) -> Result<Self, JsValue> {
+        // No frames but the requested ones
+        let stream = canvas.capture_stream_with_frame_request_rate(0.0)?;
+        let track = stream
+            .get_video_tracks()
+            .get(0)
+            .dyn_into::<web_sys::CanvasCaptureMediaStreamTrack>()?;
+        let options = web_sys::MediaRecorderOptions::new();
+        options.set_mime_type(Self::MIME_TYPE);
+        let recorder =
+            web_sys::MediaRecorder::new_with_media_stream_and_media_recorder_options(&stream, &options)?;
+
+        let chunks = js_sys::Array::new();
+        let on_data = {
+            let chunks = chunks.clone();
+            Closure::<dyn FnMut(_)>::new(move |event: web_sys::BlobEvent| {
+                if let Some(blob) = event.data() {
+                    chunks.push(&blob);
+                }
+            })
+        };
+        recorder.set_ondataavailable(Some(on_data.as_ref().unchecked_ref()));
+        recorder.start()?;
+        recorder.pause()?;
+        Ok(Self {
+            recorder,
+            track,
+            chunks,
+            _on_data: on_data,
+            frame_ms: (1000.0 / fps).round() as i32,
+        })
+    }
+
+    /// Append what the canvas shows now
+    pub async fn add_frame(&self) -> Result<(), JsValue> {
+        self.recorder.resume()?;
+        self.track.request_frame();
+        sleep(self.frame_ms).await;
+        self.recorder.pause()
+    }
+
+    pub async fn finish(self) -> Result<web_sys::Blob, JsValue> {
+        let stopped = js_sys::Promise::new(&mut |resolve, _| {
+            self.recorder.set_onstop(Some(&resolve));
+        });
+        self.recorder.stop()?;
+        wasm_bindgen_futures::JsFuture::from(stopped).await?;
+        let options = web_sys::BlobPropertyBag::new();
+        options.set_type(Self::MIME_TYPE);
+        web_sys::Blob::new_with_blob_sequence_and_options(&self.chunks, &options)
+    }
+}
+
+/// `?scene=<url>` of the page
+pub fn scene_url() -> Option<String> {
+    let search = web_sys::window()?.location().search().ok()?;
+    web_sys::UrlSearchParams::new_with_str(&search)
+        .ok()?
+        .get("scene")
+}
+
+/// Runs when the module is loaded, the page then creates viewers with `GpuDemo.create`
+#[wasm_bindgen(start)]
+pub fn start() -> Result<(), JsValue> {
+
+    std::panic::set_hook(Box::new(console_error_panic_hook::hook));
+    console_log::init_with_level(log::Level::Warn).expect("Could't initialize logger");
+    Ok(())
+}
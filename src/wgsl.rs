@@ -0,0 +1,150 @@
+/// A field of a struct shared with WGSL
+#[derive(Clone, Debug)]
+pub struct WgslField {
+    pub name: &'static str,
+    // WGSL type of the field
+    pub ty: &'static str,
+    // Rust side layout, checked against naga by the layout test
+    #[allow(dead_code)]
+    pub offset: usize,
+    #[allow(dead_code)]
+    pub size: usize,
+}
+
+/// A `#[repr(C, packed)]` struct mirrored in WGSL, implement it with `wgsl_struct!`
+pub trait WgslStruct: bytemuck::Pod {
+    // Name of the struct on the WGSL side
+    const NAME: &'static str;
+
+    fn fields() -> Vec<WgslField>;
+
+    /// WGSL declaration of the struct
+    fn wgsl() -> String {
+        let mut decl = format!("struct {} {{\n", Self::NAME);
+        for field in Self::fields() {
+            decl.push_str(&format!("  {}: {},\n", field.name, field.ty));
+        }
+        decl.push_str("}\n");
+        decl
+    }
+}
+
+// NOTE: Reading a field by value is the only way to get its type out of a packed struct
+#[doc(hidden)]
+pub fn size_of_field<T, F: Fn(&T) -> R, R>(_: F) -> usize {
+    std::mem::size_of::<R>()
+}
+
+/// Implement `WgslStruct` for a Rust struct, listing every field with its WGSL type:
+/// `wgsl_struct!(Ray as "Ray" { dir: "vec3<f32>", _pad0: "u32", ... })`
+#[macro_export]
+macro_rules! wgsl_struct {
+    ($ty:ty as $name:literal { $($field:ident : $wgsl:literal),* $(,)? }) => {
+        impl $crate::wgsl::WgslStruct for $ty {
+            const NAME: &'static str = $name;
+
+            fn fields() -> Vec<$crate::wgsl::WgslField> {
+                vec![$(
+                    $crate::wgsl::WgslField {
+                        name: stringify!($field),
+                        ty: $wgsl,
+                        offset: std::mem::offset_of!($ty, $field),
+                        size: $crate::wgsl::size_of_field(|s: &$ty| s.$field),
+                    }
+                ),*]
+            }
+        }
+    };
+}
+
+/// Generated modules handed to the shader composer, `#import "gen/<name>.wgsl"`
+pub fn modules() -> Vec<(&'static str, String)> {
+    use crate::camera::CameraLean;
+    use crate::intersection::{HitRecord, Ray};
+    use crate::settings::FrameUniform;
+    use crate::sphere::{Material, Sphere};
+
+    vec![
+        ("gen/camera.wgsl", CameraLean::wgsl()),
+        ("gen/ray.wgsl", Ray::wgsl()),
+        ("gen/hit_record.wgsl", HitRecord::wgsl()),
+        ("gen/sphere.wgsl", Sphere::wgsl()),
+        ("gen/material.wgsl", Material::wgsl()),
+        ("gen/frame.wgsl", FrameUniform::wgsl()),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::CameraLean;
+    use crate::intersection::{HitRecord, Ray};
+    use crate::settings::FrameUniform;
+    use crate::shader::Composer;
+    use crate::sphere::{Material, Sphere};
+
+    struct RustLayout {
+        name: &'static str,
+        size: usize,
+        fields: Vec<WgslField>,
+    }
+
+    fn layout<T: WgslStruct>() -> RustLayout {
+        RustLayout {
+            name: T::NAME,
+            size: std::mem::size_of::<T>(),
+            fields: T::fields(),
+        }
+    }
+
+    #[test]
+    fn wgsl_layouts_match_rust() {
+        let layouts = [
+            layout::<CameraLean>(),
+            layout::<Ray>(),
+            layout::<HitRecord>(),
+            layout::<Sphere>(),
+            layout::<Material>(),
+            layout::<FrameUniform>(),
+        ];
+        let mut seen = std::collections::HashSet::new();
+
+        let composer = Composer::builtin();
+        let variants: [(&str, &[&str]); 4] = [
+            ("rays.wgsl", &[]),
+            ("intersect.wgsl", &[]),
+            ("shade.wgsl", &[]),
+            ("shade.wgsl", &["DETERMINISTIC"]),
+        ];
+        for (entry, defines) in variants {
+            let source = composer.compose(entry, defines).unwrap();
+            let module = naga::front::wgsl::parse_str(&source)
+                .unwrap_or_else(|e| panic!("{entry}: {}", e.emit_to_string(&source)));
+            let mut layouter = naga::proc::Layouter::default();
+            layouter.update(module.to_ctx()).unwrap();
+
+            for (_, ty) in module.types.iter() {
+                let naga::TypeInner::Struct { members, span } = &ty.inner else {
+                    continue;
+                };
+                let Some(rust) = layouts.iter().find(|l| ty.name.as_deref() == Some(l.name)) else {
+                    continue;
+                };
+                seen.insert(rust.name);
+
+                assert_eq!(*span as usize, rust.size, "{entry}: size of {}", rust.name);
+                assert_eq!(members.len(), rust.fields.len(), "{entry}: fields of {}", rust.name);
+                for (member, field) in members.iter().zip(rust.fields.iter()) {
+                    let what = format!("{entry}: {}.{}", rust.name, field.name);
+                    assert_eq!(member.name.as_deref(), Some(field.name), "{what}");
+                    assert_eq!(member.offset as usize, field.offset, "{what} offset");
+                    assert_eq!(layouter[member.ty].size as usize, field.size, "{what} size");
+                }
+            }
+        }
+
+        for rust in layouts.iter() {
+            assert!(seen.contains(rust.name), "{} is not used by any shader", rust.name);
+        }
+    }
+}
@@ -0,0 +1,271 @@
+use std::collections::HashMap;
+use std::ops::Range;
+
+use crate::scene::{self, SceneError};
+use crate::sphere::{Material, Sphere};
+
+/// Handle of a sphere, valid until the sphere is removed
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct SphereHandle(u32);
+
+impl SphereHandle {
+    /// Number identifying the sphere, for callers that can't hold the handle itself
+    pub fn id(self) -> u32 {
+        self.0
+    }
+
+    /// The handle of `id`, edits through it fail if no such sphere exists
+    pub fn from_id(id: u32) -> Self {
+        Self(id)
+    }
+}
+
+/// Handle of a material, materials are never removed so it stays valid
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct MaterialHandle(u32);
+
+impl MaterialHandle {
+    /// Index of the material in `Scene::materials`, the id spheres refer to
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+// Union of the elements changed since the last flush
+#[derive(Clone, Debug, Default)]
+struct Dirty(Option<Range<usize>>);
+
+impl Dirty {
+    fn mark(&mut self, range: Range<usize>) {
+        self.0 = Some(match self.0.take() {
+            Some(dirty) => dirty.start.min(range.start)..dirty.end.max(range.end),
+            None => range,
+        });
+    }
+}
+
+/// Materials and spheres as edited at runtime.
+/// Spheres are kept packed in the order the shaders see them, removing one moves the last
+/// sphere into its slot. Edits are recorded as dirty ranges, see `Renderer::scene_mut`
+#[derive(Clone, Debug)]
+pub struct Scene {
+    materials: Vec<Material>,
+    spheres: Vec<Sphere>,
+    // Handle of each entry of `spheres`
+    handles: Vec<SphereHandle>,
+    slots: HashMap<SphereHandle, usize>,
+    next_handle: u32,
+    dirty_materials: Dirty,
+    dirty_spheres: Dirty,
+}
+
+impl Default for Scene {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Scene {
+    pub fn new() -> Self {
+        Self {
+            materials: Vec::new(),
+            spheres: Vec::new(),
+            handles: Vec::new(),
+            slots: HashMap::new(),
+            next_handle: 0,
+            // Even an empty scene has to be bound once
+            dirty_materials: Dirty(Some(0..0)),
+            dirty_spheres: Dirty(Some(0..0)),
+        }
+    }
+
+    /// A scene holding `materials` and `spheres`, spheres refer to materials by index
+    pub fn from_world(materials: &[Material], spheres: &[Sphere]) -> Result<Self, SceneError> {
+        scene::validate_world(materials, spheres)?;
+        let mut scene = Self::new();
+        scene.materials = materials.to_vec();
+        for sphere in spheres {
+            scene.push_sphere(*sphere);
+        }
+        scene.dirty_materials.mark(0..materials.len());
+        scene.dirty_spheres.mark(0..spheres.len());
+        Ok(scene)
+    }
+
+    pub fn materials(&self) -> &[Material] {
+        &self.materials
+    }
+
+    pub fn spheres(&self) -> &[Sphere] {
+        &self.spheres
+    }
+
+    /// Handles of `spheres()`, in the same order
+    pub fn sphere_handles(&self) -> &[SphereHandle] {
+        &self.handles
+    }
+
+    pub fn material_handle(&self, index: usize) -> Option<MaterialHandle> {
+        (index < self.materials.len()).then_some(MaterialHandle(index as u32))
+    }
+
+    pub fn sphere(&self, handle: SphereHandle) -> Option<&Sphere> {
+        self.slots.get(&handle).map(|slot| &self.spheres[*slot])
+    }
+
+    pub fn material(&self, handle: MaterialHandle) -> Option<&Material> {
+        self.materials.get(handle.index())
+    }
+
+    pub fn add_material(&mut self, material: Material) -> Result<MaterialHandle, SceneError> {
+        let index = self.materials.len();
+        scene::validate_material(&material, &format!("materials[{index}]"))?;
+        self.materials.push(material);
+        self.dirty_materials.mark(index..index + 1);
+        Ok(MaterialHandle(index as u32))
+    }
+
+    pub fn update_material(
+        &mut self,
+        handle: MaterialHandle,
+        material: Material,
+    ) -> Result<(), SceneError> {
+        let index = handle.index();
+        if index >= self.materials.len() {
+            return Err(SceneError::UnknownHandle {
+                kind: "material",
+                id: handle.0,
+            });
+        }
+        scene::validate_material(&material, &format!("materials[{index}]"))?;
+        self.materials[index] = material;
+        self.dirty_materials.mark(index..index + 1);
+        Ok(())
+    }
+
+    pub fn add_sphere(
+        &mut self,
+        position: [f32; 3],
+        radius: f32,
+        material: MaterialHandle,
+    ) -> Result<SphereHandle, SceneError> {
+        let sphere = Sphere::new(position, material.0 as i32, radius);
+        let path = format!("sphere {}", self.next_handle);
+        scene::validate_sphere(&sphere, self.materials.len(), &path)?;
+        let handle = self.push_sphere(sphere);
+        let slot = self.spheres.len() - 1;
+        self.dirty_spheres.mark(slot..slot + 1);
+        Ok(handle)
+    }
+
+    pub fn remove(&mut self, handle: SphereHandle) -> Result<(), SceneError> {
+        let slot = self
+            .slots
+            .remove(&handle)
+            .ok_or(SceneError::UnknownHandle {
+                kind: "sphere",
+                id: handle.0,
+            })?;
+        self.spheres.swap_remove(slot);
+        self.handles.swap_remove(slot);
+        // The last sphere moved into the hole
+        if let Some(moved) = self.handles.get(slot) {
+            self.slots.insert(*moved, slot);
+            self.dirty_spheres.mark(slot..slot + 1);
+        } else {
+            // Shrinking is enough when the last one went
+            self.dirty_spheres.mark(slot..slot);
+        }
+        Ok(())
+    }
+
+    /// Move and scale a sphere
+    pub fn set_transform(
+        &mut self,
+        handle: SphereHandle,
+        position: [f32; 3],
+        radius: f32,
+    ) -> Result<(), SceneError> {
+        self.edit_sphere(handle, |sphere| {
+            sphere.position = position;
+            sphere.radius = radius;
+        })
+    }
+
+    /// Give a sphere another material
+    pub fn set_material(
+        &mut self,
+        handle: SphereHandle,
+        material: MaterialHandle,
+    ) -> Result<(), SceneError> {
+        self.edit_sphere(handle, |sphere| sphere.material_id = material.0 as i32)
+    }
+
+    /// Whether edits are waiting to be flushed
+    pub fn is_dirty(&self) -> bool {
+        self.dirty_materials.0.is_some() || self.dirty_spheres.0.is_some()
+    }
+
+    /// Element ranges of materials and spheres changed since the last call
+    pub(crate) fn take_dirty(&mut self) -> (Option<Range<usize>>, Option<Range<usize>>) {
+        (self.dirty_materials.0.take(), self.dirty_spheres.0.take())
+    }
+
+    fn push_sphere(&mut self, sphere: Sphere) -> SphereHandle {
+        let handle = SphereHandle(self.next_handle);
+        self.next_handle += 1;
+        self.slots.insert(handle, self.spheres.len());
+        self.spheres.push(sphere);
+        self.handles.push(handle);
+        handle
+    }
+
+    fn edit_sphere(
+        &mut self,
+        handle: SphereHandle,
+        edit: impl FnOnce(&mut Sphere),
+    ) -> Result<(), SceneError> {
+        let slot = *self
+            .slots
+            .get(&handle)
+            .ok_or(SceneError::UnknownHandle {
+                kind: "sphere",
+                id: handle.0,
+            })?;
+        let mut sphere = self.spheres[slot];
+        edit(&mut sphere);
+        scene::validate_sphere(&sphere, self.materials.len(), &format!("sphere {}", handle.0))?;
+        self.spheres[slot] = sphere;
+        self.dirty_spheres.mark(slot..slot + 1);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn handles_survive_removal_and_edits_stay_dirty() {
+        let mut scene = Scene::from_world(&[Material::default()], &[]).unwrap();
+        let material = scene.material_handle(0).unwrap();
+        let a = scene.add_sphere([0.0; 3], 1.0, material).unwrap();
+        let b = scene.add_sphere([1.0, 0.0, 0.0], 2.0, material).unwrap();
+        let c = scene.add_sphere([2.0, 0.0, 0.0], 3.0, material).unwrap();
+        scene.take_dirty();
+
+        scene.remove(a).unwrap();
+        assert_eq!(scene.sphere_handles(), [c, b]);
+        assert_eq!({ scene.sphere(c).unwrap().radius }, 3.0);
+        assert_eq!(scene.take_dirty(), (None, Some(0..1)));
+
+        scene.set_transform(b, [5.0; 3], 4.0).unwrap();
+        assert_eq!(scene.take_dirty(), (None, Some(1..2)));
+        assert!(matches!(
+            scene.remove(a),
+            Err(SceneError::UnknownHandle { kind: "sphere", .. })
+        ));
+        assert!(scene.set_transform(c, [0.0; 3], -1.0).is_err());
+        assert!(!scene.is_dirty());
+    }
+}
@@ -0,0 +1,271 @@
+//! Golden image tests: render the reference scenes at low resolution with a fixed seed and
+//! compare them with the PNGs checked in under tests/golden.
+//!
+//! `GOLDEN_BLESS=1 cargo test --test golden` rewrites the references from the CPU renderer.
+//! On failure the render and a diff image are written next to the test binary, under
+//! target/tmp/golden.
+
+use std::path::{Path, PathBuf};
+
+use gpudemo::cpu::CpuRenderer;
+use gpudemo::headless::{self, HeadlessError, HeadlessOptions};
+use gpudemo::scene::SceneDesc;
+use gpudemo::settings::RenderSettings;
+use gpudemo::{Image, Renderer};
+
+const WIDTH: u32 = 96;
+const HEIGHT: u32 = 54;
+
+// Name of the reference, scene file relative to the crate root
+const SCENES: &[(&str, &str)] = &[
+    ("default", "scenes/default.json"),
+    ("lambert", "tests/golden/lambert.json"),
+    ("metal", "tests/golden/metal.json"),
+];
+
+// A channel further off than this counts the pixel as an outlier
+const OUTLIER_DELTA: u8 = 64;
+
+struct Tolerance {
+    // Mean absolute error over all channels, in 8 bit steps
+    mean: f64,
+    // Fraction of outlier pixels
+    outliers: f64,
+}
+
+// Same code as the references, only libm differences
+const CPU_TOLERANCE: Tolerance = Tolerance {
+    mean: 0.5,
+    outliers: 0.002,
+};
+
+// Same random numbers in the deterministic mode, the paths only drift with float precision
+const GPU_TOLERANCE: Tolerance = Tolerance {
+    mean: 2.0,
+    outliers: 0.01,
+};
+
+fn settings() -> RenderSettings {
+    RenderSettings {
+        spp: 32,
+        max_bounces: 16,
+        seed: 3,
+        deterministic: true,
+        ..Default::default()
+    }
+}
+
+fn root() -> &'static Path {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+}
+
+fn reference_path(name: &str) -> PathBuf {
+    root().join("tests/golden").join(format!("{name}.png"))
+}
+
+fn blessing() -> bool {
+    std::env::var_os("GOLDEN_BLESS").is_some_and(|v| v != "0")
+}
+
+// The scene with the test settings
+fn load_scene(scene: &str) -> SceneDesc {
+    let mut desc = SceneDesc::load(root().join(scene)).unwrap_or_else(|e| panic!("{scene}: {e}"));
+    desc.settings = settings();
+    desc
+}
+
+fn read_png(path: &Path) -> Image {
+    let file = std::fs::File::open(path)
+        .unwrap_or_else(|e| panic!("{}: {e}, bless the references first", path.display()));
+    let mut reader = png::Decoder::new(std::io::BufReader::new(file))
+        .read_info()
+        .unwrap();
+    let mut data = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut data).unwrap();
+    assert_eq!(info.color_type, png::ColorType::Rgba, "{}", path.display());
+    assert_eq!(info.bit_depth, png::BitDepth::Eight, "{}", path.display());
+    data.truncate(info.buffer_size());
+    Image {
+        width: info.width,
+        height: info.height,
+        data,
+    }
+}
+
+fn write_png(path: &Path, image: &Image) {
+    std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+    let file = std::fs::File::create(path).unwrap();
+    let mut encoder = png::Encoder::new(std::io::BufWriter::new(file), image.width, image.height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder
+        .write_header()
+        .unwrap()
+        .write_image_data(&image.data)
+        .unwrap();
+}
+
+struct Diff {
+    mean: f64,
+    outliers: f64,
+    // Absolute difference, scaled up to be visible
+    image: Image,
+}
+
+fn diff(actual: &Image, expected: &Image) -> Diff {
+    assert_eq!(
+        (actual.width, actual.height),
+        (expected.width, expected.height),
+        "image size"
+    );
+    let mut total = 0u64;
+    let mut outliers = 0usize;
+    let mut data = Vec::with_capacity(actual.data.len());
+    for (a, e) in actual.data.chunks(4).zip(expected.data.chunks(4)) {
+        let delta: Vec<u8> = a.iter().zip(e).map(|(a, e)| a.abs_diff(*e)).collect();
+        total += delta.iter().map(|d| *d as u64).sum::<u64>();
+        if delta.iter().any(|d| *d > OUTLIER_DELTA) {
+            outliers += 1;
+        }
+        data.extend(delta[..3].iter().map(|d| d.saturating_mul(4)));
+        data.push(255);
+    }
+    let pixels = (actual.width * actual.height) as usize;
+    Diff {
+        mean: total as f64 / actual.data.len() as f64,
+        outliers: outliers as f64 / pixels as f64,
+        image: Image {
+            width: actual.width,
+            height: actual.height,
+            data,
+        },
+    }
+}
+
+/// Compare against the reference, returns a description of the failure if any
+fn check(name: &str, renderer: &str, actual: &Image, tolerance: &Tolerance) -> Option<String> {
+    let expected = read_png(&reference_path(name));
+    let diff = diff(actual, &expected);
+    if diff.mean <= tolerance.mean && diff.outliers <= tolerance.outliers {
+        return None;
+    }
+
+    let out = Path::new(env!("CARGO_TARGET_TMPDIR")).join("golden");
+    let actual_path = out.join(format!("{name}.{renderer}.png"));
+    let diff_path = out.join(format!("{name}.{renderer}.diff.png"));
+    write_png(&actual_path, actual);
+    write_png(&diff_path, &diff.image);
+    Some(format!(
+        "{name} ({renderer}): mean error {:.3} > {}, or outliers {:.4} > {}, see {} and {}",
+        diff.mean,
+        tolerance.mean,
+        diff.outliers,
+        tolerance.outliers,
+        actual_path.display(),
+        diff_path.display()
+    ))
+}
+
+fn render_cpu(scene: &str) -> Image {
+    let mut renderer = CpuRenderer::new();
+    renderer.load_scene(&load_scene(scene)).unwrap();
+    assert!(renderer.resize(WIDTH, HEIGHT));
+    renderer.render_samples(|_, _| {})
+}
+
+// None when the machine has no GPU adapter, the GPU tests are skipped then
+fn gpu_renderer() -> Option<Renderer> {
+    match pollster::block_on(headless::create_renderer(&HeadlessOptions::default())) {
+        Ok(renderer) => Some(renderer),
+        Err(HeadlessError::NoAdapter) => {
+            eprintln!("no GPU adapter, skipping");
+            None
+        }
+        Err(e) => panic!("{e}"),
+    }
+}
+
+#[test]
+fn cpu_matches_references() {
+    let mut failures = Vec::new();
+    for (name, scene) in SCENES {
+        let image = render_cpu(scene);
+        if blessing() {
+            write_png(&reference_path(name), &image);
+            continue;
+        }
+        failures.extend(check(name, "cpu", &image, &CPU_TOLERANCE));
+    }
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
+
+#[test]
+fn gpu_matches_references() {
+    // The references come from the CPU renderer
+    if blessing() {
+        return;
+    }
+    let Some(mut renderer) = gpu_renderer() else {
+        return;
+    };
+
+    let mut failures = Vec::new();
+    for (name, scene) in SCENES {
+        renderer.load_scene(&load_scene(scene)).unwrap();
+        assert!(renderer.resize(WIDTH, HEIGHT));
+        let image = pollster::block_on(headless::render_samples(&mut renderer, |_, _| {})).unwrap();
+        failures.extend(check(name, "gpu", &image, &GPU_TOLERANCE));
+    }
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
+
+#[test]
+fn gpu_deterministic_is_repeatable() {
+    let Some(mut renderer) = gpu_renderer() else {
+        return;
+    };
+    renderer.load_scene(&load_scene(SCENES[0].1)).unwrap();
+    assert!(renderer.resize(WIDTH, HEIGHT));
+
+    let first = pollster::block_on(headless::render_samples(&mut renderer, |_, _| {})).unwrap();
+    // Frames past `spp` leave the image alone, like a viewer that keeps drawing
+    for _ in 0..4 {
+        renderer.render_offscreen();
+    }
+    assert_eq!(renderer.sample_count(), settings().spp);
+    let again = pollster::block_on(renderer.read_frame()).unwrap();
+    let second = pollster::block_on(headless::render_samples(&mut renderer, |_, _| {})).unwrap();
+    assert!(first.data == again.data, "frames past spp changed the image");
+    assert!(first.data == second.data, "two renders differ");
+}
+
+#[test]
+fn gpu_layers_match_cpu() {
+    let Some(mut renderer) = gpu_renderer() else {
+        return;
+    };
+    let scene = load_scene(SCENES[0].1);
+    renderer.load_scene(&scene).unwrap();
+    assert!(renderer.resize(WIDTH, HEIGHT));
+    let gpu = pollster::block_on(headless::render_layers(&mut renderer, |_, _| {})).unwrap();
+
+    let mut cpu = CpuRenderer::new();
+    cpu.load_scene(&scene).unwrap();
+    assert!(cpu.resize(WIDTH, HEIGHT));
+    cpu.render_samples(|_, _| {});
+    let cpu = cpu.layers();
+
+    // Primary hits only drift at silhouettes
+    let pixels = (WIDTH * HEIGHT) as usize;
+    let same_id = gpu.object_id.iter().zip(&cpu.object_id).filter(|(a, b)| a == b).count();
+    assert!(same_id as f64 / pixels as f64 > 0.99, "{same_id} of {pixels} ids match");
+    assert!(gpu.object_id.contains(&0) && gpu.object_id.iter().any(|id| *id > 1));
+    let error = gpu
+        .beauty
+        .iter()
+        .zip(&cpu.beauty)
+        .map(|(a, b)| (0..3).map(|i| (a[i] - b[i]).abs()).sum::<f32>() as f64)
+        .sum::<f64>()
+        / (3 * pixels) as f64;
+    assert!(error < 0.01, "beauty mean error {error}");
+}
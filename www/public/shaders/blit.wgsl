@@ -0,0 +1,27 @@
+// Draws the frame texture over a surface it can't be copied to, scaled to the surface size
+
+@group(0) @binding(0)
+var frame_tex: texture_2d<f32>;
+
+@group(0) @binding(1)
+var frame_sampler: sampler;
+
+struct VertexOut {
+  @builtin(position) position: vec4<f32>,
+  @location(0) uv: vec2<f32>,
+}
+
+// One triangle covering the whole target
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOut {
+  let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+  var out: VertexOut;
+  out.position = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+  out.uv = uv;
+  return out;
+}
+
+@fragment
+fn fs_main(in: VertexOut) -> @location(0) vec4<f32> {
+  return textureSample(frame_tex, frame_sampler, in.uv);
+}
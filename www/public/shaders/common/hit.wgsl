@@ -0,0 +1,13 @@
+#import "gen/hit_record.wgsl"
+
+// ray_dir does not need to be normalized
+fn set_hit_orientation (ray_dir: vec3<f32>,
+  irec : ptr<function, HitRecord>) {
+
+  // Ray started from inside and hit the surface from the back
+  if dot((*irec).normal, ray_dir) > 0.0 {
+    // NOTE: Make it point outward
+    (*irec).normal = -(*irec).normal;
+    (*irec).flags = (*irec).flags | 0x1;
+  }
+}
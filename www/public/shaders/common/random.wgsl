@@ -0,0 +1,51 @@
+// Random numbers of the shade pass.
+// By default a float hash of the pixel and a float seed, cheap but its output depends on
+// the precision of sin(). With DETERMINISTIC an integer PCG hash of the pixel, the sample
+// and the seed, which gives the same numbers on every adapter
+
+#ifdef DETERMINISTIC
+var<private> rng_state: u32;
+
+// PCG hash, Jarzynski and Olano 2020
+fn pcg(v: u32) -> u32 {
+  let state = v * 747796405u + 2891336453u;
+  let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+  return (word >> 22u) ^ word;
+}
+
+fn rand_init(pixel_index: u32, sample: u32, seed: u32) {
+  rng_state = pcg(pixel_index ^ pcg(sample ^ pcg(seed)));
+}
+
+// [0, 1) Random value, 24 bits so the float is exact
+fn rand(seed: ptr<function, f32>, pixel: vec2<f32>) -> f32 {
+  rng_state = pcg(rng_state);
+  return f32(rng_state >> 8u) / 16777216.0;
+}
+
+fn rand3(seed: ptr<function, f32>, pixel: vec2<f32>) -> vec3<f32> {
+  let x = rand(seed, pixel);
+  let y = rand(seed, pixel);
+  let z = rand(seed, pixel);
+  return vec3<f32>(x, y, z);
+}
+#else
+fn rand_init(pixel_index: u32, sample: u32, seed: u32) {}
+
+// [0, 1] Random value
+fn rand(seed: ptr<function, f32>, pixel: vec2<f32>) -> f32
+{
+    let result: f32 = fract(sin(*seed / 100.0f * dot(pixel, vec2<f32>(12.9898f, 78.233f))) * 43758.5453f);
+    *seed = *seed + 1.0f;
+    return result;
+}
+
+fn rand3(seed: ptr<function, f32>, pixel: vec2<f32>) -> vec3<f32>
+{
+    let x: f32 = fract(sin(*seed / 100.0f * dot(pixel, vec2<f32>(12.9898f, 78.233f))) * 43758.5453f);
+    let y: f32 = fract(sin((*seed+1) / 100.0f * dot(pixel, vec2<f32>(12.9898f, 78.233f))) * 43758.5453f);
+    let z: f32 = fract(sin((*seed+2) / 100.0f * dot(pixel, vec2<f32>(12.9898f, 78.233f))) * 43758.5453f);
+    *seed = *seed + 3;
+    return vec3<f32>(x, y, z);
+}
+#endif
@@ -0,0 +1,25 @@
+#import "gen/sphere.wgsl"
+
+fn hit_sphere(center:vec3<f32>, radius: f32, ro: vec3<f32>, rv: vec3<f32>, 
+  tmin: f32, tmax: f32) -> f32 {
+
+  let oc = center - ro;
+  let a = dot(rv, rv) ; 
+  let h = dot(rv, oc);
+  let c = dot(oc, oc) - radius*radius;
+  let discriminant = h*h - a*c;
+
+  if discriminant < 0.0 {
+      return -1.0;
+  }
+  let sqroot = sqrt(discriminant);
+
+  var root = (h - sqroot) / a;
+  if (root <= tmin || root >= tmax){
+    root = (h + sqroot) / a;
+    if (root <= tmin || root >= tmax){
+      return -1.0;
+    }
+  }
+  return root;
+}
@@ -1,28 +1,7 @@
-
-struct Ray {
-  dir: vec3<f32>,
-  _pad0: u32,
-  o: vec3<f32>,
-  _pad1: u32,
-}
-struct Sphere {
-   position: vec3<f32>,
-   radius: f32,
-   material_id: i32,
-   _pad0x: u32,
-   _pad0y: u32,
-   _pad0z: u32,
-}
-
-struct HitRecord {
-  point: vec4<f32>,
-  normal: vec3<f32>,
-  flags: u32,
-  material_id: i32,
-  _pad0x: u32,
-  _pad0y: u32,
-  _pad0z: u32,
-}
+#import "gen/ray.wgsl"
+#import "common/sphere.wgsl"
+#import "common/hit.wgsl"
+#import "gen/frame.wgsl"
 
 @group(0) @binding(2) 
 var<storage, read_write> rays: array<Ray>;
@@ -36,43 +15,14 @@ var<storage> world_spheres: array<Sphere>;
 
 @group(3) @binding(5) 
 var<uniform> dims: vec2<u32>;
+@group(3) @binding(7) 
+var<uniform> frame: Frame;
 
-fn hit_sphere(center:vec3<f32>, radius: f32, ro: vec3<f32>, rv: vec3<f32>, 
-  tmin: f32, tmax: f32) -> f32 {
-
-  let oc = center - ro;
-  let a = dot(rv, rv) ; 
-  let h = dot(rv, oc);
-  let c = dot(oc, oc) - radius*radius;
-  let discriminant = h*h - a*c;
-
-  if discriminant < 0.0 {
-      return -1.0;
-  }
-  let sqroot = sqrt(discriminant);
-
-  var root = (h - sqroot) / a;
-  if (root <= tmin || root >= tmax){
-    root = (h + sqroot) / a;
-    if (root <= tmin || root >= tmax){
-      return -1.0;
-    }
-  }
-  return root;
-}
-
-fn set_hit_orientation (ray: ptr<storage, vec3<f32>, read_write>,
-  irec : ptr<storage, HitRecord, read_write>) {
-
-  // Ray started from inside and hit the surface from the back
-  if dot(irec.normal, normalize(*ray)) > 0.0 {
-    // NOTE: Make it point outward
-    irec.normal = -irec.normal;
-    irec.flags = irec.flags | 0x1;
-  }
-}
+// Workgroup size, see `PassDesc` in src/graph.rs
+override WORKGROUP_X: u32 = 8u;
+override WORKGROUP_Y: u32 = 8u;
 
-@compute @workgroup_size(8,8)
+@compute @workgroup_size(WORKGROUP_X, WORKGROUP_Y)
 fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
   let width = dims.x;
   let height = dims.y;
@@ -93,7 +43,7 @@ fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
     let sphere_radius = world_spheres[i].radius;
 
     // Cast ray
-    let s = hit_sphere(sphere_center, sphere_radius, o, dir, 0.001, 99999.0);
+    let s = hit_sphere(sphere_center, sphere_radius, o, dir, frame.tmin, frame.tmax);
 
     let is_valid_hit = s > 0.0 && (closest_hit < 0.0 || s < closest_hit);
 
@@ -103,9 +53,12 @@ fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
 
   let hit_point = o + dir * closest_hit;
   let normal = normalize(hit_point - world_spheres[closest_sphere].position);
-  rec[ray_id].point = vec4<f32>(hit_point.xyz, closest_hit);
-  rec[ray_id].normal = normal;
-  rec[ray_id].material_id = world_spheres[closest_sphere].material_id;
-  set_hit_orientation(&rays[ray_id].dir, &rec[ray_id]);
+  var hit = rec[ray_id];
+  hit.point = vec4<f32>(hit_point.xyz, closest_hit);
+  hit.normal = normal;
+  hit.material_id = world_spheres[closest_sphere].material_id;
+  hit.object_id = closest_sphere;
+  set_hit_orientation(dir, &hit);
+  rec[ray_id] = hit;
   
 }
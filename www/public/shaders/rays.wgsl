@@ -1,20 +1,5 @@
-struct Camera {
-  pixeloo: vec3<f32>,
-  _pad0: u32,
-  pixel_delta_u: vec3<f32>,
-  _pad1: u32,
-  pixel_delta_v: vec3<f32>,
-  _pad2: u32,
-  pos: vec3<f32>,
-  _pad3: u32,
-}
-
-struct Ray {
-  dir: vec3<f32>,
-  _pad0: u32,
-  o: vec3<f32>,
-  _pad1: u32,
-}
+#import "gen/camera.wgsl"
+#import "gen/ray.wgsl"
 
 @group(0) @binding(0) 
 var<uniform> camera: Camera;
@@ -25,7 +10,11 @@ var<storage, read_write> rays: array<Ray>;
 @group(2) @binding(5) 
 var<uniform> dims: vec2<u32>;
 
-@compute @workgroup_size(8,8)
+// Workgroup size, see `PassDesc` in src/graph.rs
+override WORKGROUP_X: u32 = 8u;
+override WORKGROUP_Y: u32 = 8u;
+
+@compute @workgroup_size(WORKGROUP_X, WORKGROUP_Y)
 fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
   let width = dims.x;
   let height = dims.y;
@@ -1,37 +1,9 @@
-struct HitRecord {
-  point: vec4<f32>,
-  normal: vec3<f32>,
-  flags: u32,
-  material_id: i32,
-  _pad0x: u32,
-  _pad0y: u32,
-  _pad0z: u32,
-}
-
-struct Sphere {
-   position: vec3<f32>,
-   radius: f32,
-   material_id: i32,
-   _pad0x: u32,
-   _pad0y: u32,
-   _pad0z: u32,
-}
-
-struct Material {
- albedo: vec4<f32>,
- kind: u32,
- fuzz: f32,
- _pad0x : u32,
- _pad0y : u32,
-}
-
-struct Ray {
-  dir: vec3<f32>,
-  _pad0: u32,
-  o: vec3<f32>,
-  _pad1: u32,
-}
-
+#import "gen/ray.wgsl"
+#import "common/sphere.wgsl"
+#import "common/hit.wgsl"
+#import "gen/material.wgsl"
+#import "gen/frame.wgsl"
+#import "common/random.wgsl"
 
 @group(0) @binding(4) 
 var<storage, read_write> rec: array<HitRecord>;
@@ -45,11 +17,14 @@ var<uniform> dims: vec2<u32>;
 
 @group(2) @binding(1) 
 var outputTexture: texture_storage_2d<rgba8unorm, write>;
+// Sum of the samples of each pixel
+@group(2) @binding(8) 
+var<storage, read_write> accum: array<vec4<f32>>;
 
 @group(3) @binding(6) 
 var<storage> materials: array<Material>;
 @group(3) @binding(7) 
-var<uniform> u_seed: f32;
+var<uniform> frame: Frame;
 
 
 fn is_near_zero(v: vec3<f32>) -> bool {
@@ -57,23 +32,6 @@ fn is_near_zero(v: vec3<f32>) -> bool {
   return dot(v, v) < (epsilon * epsilon);
 }
 
-// [0, 1] Random value
-fn rand(seed: ptr<function, f32>, pixel: vec2<f32>) -> f32
-{
-    let result: f32 = fract(sin(*seed / 100.0f * dot(pixel, vec2<f32>(12.9898f, 78.233f))) * 43758.5453f);
-    *seed = *seed + 1.0f;
-    return result;
-}
-
-fn rand3(seed: ptr<function, f32>, pixel: vec2<f32>) -> vec3<f32>
-{
-    let x: f32 = fract(sin(*seed / 100.0f * dot(pixel, vec2<f32>(12.9898f, 78.233f))) * 43758.5453f);
-    let y: f32 = fract(sin((*seed+1) / 100.0f * dot(pixel, vec2<f32>(12.9898f, 78.233f))) * 43758.5453f);
-    let z: f32 = fract(sin((*seed+2) / 100.0f * dot(pixel, vec2<f32>(12.9898f, 78.233f))) * 43758.5453f);
-    *seed = *seed + 3;
-    return vec3<f32>(x, y, z);
-}
-
 fn random_in_sphere(seed: ptr<function, f32>, pixel: vec2<f32>) -> vec3<f32> {
   while (true){
     var rand_vec = rand3(seed, pixel);
@@ -101,42 +59,6 @@ fn reflect(dir: vec3<f32>, normal: vec3<f32>) -> vec3<f32> {
    return dir - (2.0 * (dot(dir, normal) * normal));
 }
 
-fn hit_sphere(center:vec3<f32>, radius: f32, ro: vec3<f32>, rv: vec3<f32>, 
-  tmin: f32, tmax: f32) -> f32 {
-
-  let oc = center - ro;
-  let a = dot(rv, rv) ; 
-  let h = dot(rv, oc);
-  let c = dot(oc, oc) - radius*radius;
-  let discriminant = h*h - a*c;
-
-  if discriminant < 0.0 {
-      return -1.0;
-  }
-  let sqroot = sqrt(discriminant);
-
-  var root = (h - sqroot) / a;
-  if (root <= tmin || root >= tmax){
-    root = (h + sqroot) / a;
-    if (root <= tmin || root >= tmax){
-      return -1.0;
-    }
-  }
-  return root;
-}
-
-// ray_dir is normalized
-fn set_hit_orientation (ray_dir: vec3<f32>,
-  irec : ptr<function, HitRecord>) {
-
-  // Ray started from inside and hit the surface from the back
-  if dot(irec.normal, ray_dir) > 0.0 {
-    // NOTE: Make it point outward
-    irec.normal = -irec.normal;
-    irec.flags = irec.flags | 0x1;
-  }
-}
-
 fn hit_any(ray: ptr<function, Ray>, new_rec : ptr<function, HitRecord>) -> bool {
   var closest_hit: f32 = -1.0;
   var closest_sphere = 0u;
@@ -145,9 +67,8 @@ fn hit_any(ray: ptr<function, Ray>, new_rec : ptr<function, HitRecord>) -> bool
     let sphere_center = world_spheres[i].position;
     let sphere_radius = world_spheres[i].radius;
 
-    let eps = 0.01;
     // Cast ray
-    let s = hit_sphere(sphere_center, sphere_radius, ray.o, ray.dir, eps, 99999.0);
+    let s = hit_sphere(sphere_center, sphere_radius, (*ray).o, (*ray).dir, frame.bounce_tmin, frame.tmax);
 
     let is_valid_hit = s > 0.0 && (closest_hit < 0.0 || s < closest_hit);
 
@@ -159,13 +80,14 @@ fn hit_any(ray: ptr<function, Ray>, new_rec : ptr<function, HitRecord>) -> bool
     return false;
   }
 
-  let hit_point = ray.o + ray.dir * closest_hit;
+  let hit_point = (*ray).o + (*ray).dir * closest_hit;
   let normal = normalize(hit_point - world_spheres[closest_sphere].position);
-  new_rec.point = vec4<f32>(hit_point.xyz, closest_hit);
-  new_rec.normal = normal;
-  new_rec.material_id = world_spheres[closest_sphere].material_id;
+  (*new_rec).point = vec4<f32>(hit_point.xyz, closest_hit);
+  (*new_rec).normal = normal;
+  (*new_rec).material_id = world_spheres[closest_sphere].material_id;
+  (*new_rec).object_id = closest_sphere;
   
-  set_hit_orientation(ray.dir, new_rec);
+  set_hit_orientation((*ray).dir, new_rec);
   return true;
 }
 
@@ -191,14 +113,14 @@ fn scatter_lambert(hit_info: ptr<function, HitRecord>,
   pixel: vec2<f32>,
   ray :ptr<function, Ray>) -> bool {
 
-  let rand_vec = random_in_hemisphere(hit_info.normal, seed, pixel);
-  var dir = normalize(rand_vec + hit_info.normal);
+  let rand_vec = random_in_hemisphere((*hit_info).normal, seed, pixel);
+  var dir = normalize(rand_vec + (*hit_info).normal);
   if is_near_zero(dir) {
-    dir = hit_info.normal;
+    dir = (*hit_info).normal;
   }
 
-  ray.dir = dir;
-  ray.o = hit_info.point.xyz;
+  (*ray).dir = dir;
+  (*ray).o = (*hit_info).point.xyz;
   return true;
 }
 
@@ -208,18 +130,26 @@ fn scatter_metal(vec: vec3<f32>,
   seed: ptr<function, f32>, 
   pixel: vec2<f32>,
   ray: ptr<function, Ray>) -> bool {
-  var dir = reflect(vec, hit_info.normal);
-  dir = normalize(normalize(dir) + fuzz * random_in_hemisphere(hit_info.normal, seed, pixel));
+  var dir = reflect(vec, (*hit_info).normal);
+  dir = normalize(normalize(dir) + fuzz * random_in_hemisphere((*hit_info).normal, seed, pixel));
 
-  ray.dir = dir;
-  ray.o = hit_info.point.xyz;
+  (*ray).dir = dir;
+  (*ray).o = (*hit_info).point.xyz;
   // NOTE: In theory never false since I use random in hemisphere
-  let ret = dot(dir, hit_info.normal) > 0.0;
+  let ret = dot(dir, (*hit_info).normal) > 0.0;
   return ret;
 }
 
 
-@compute @workgroup_size(8,8)
+// Bounce depth baked into the pipeline, so the bounce loop has a constant bound.
+// Negative takes `frame.max_bounces`
+override MAX_BOUNCES: i32 = -1;
+
+// Workgroup size, see `PassDesc` in src/graph.rs
+override WORKGROUP_X: u32 = 8u;
+override WORKGROUP_Y: u32 = 8u;
+
+@compute @workgroup_size(WORKGROUP_X, WORKGROUP_Y)
 fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
 
   let width = dims.x;
@@ -234,11 +164,13 @@ fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
   var color = vec4<f32>(0.0, 0.0, 0.0, 1.0);
 
   // How much ping-pong ===========
-  let max_bounce = 100; 
+  let max_bounce = select(i32(frame.max_bounces), MAX_BOUNCES, MAX_BOUNCES >= 0);
   // ==============================
 
-  var seed = u_seed;
+  // Offset every sample into its own run of seeds
+  var seed = frame.seed + f32(frame.sample) * 0.618;
   let pseed: ptr<function, f32> = &seed;
+  rand_init(idx, frame.sample, frame.int_seed);
 
   if rec[idx].point.w > 0.0 && rec[idx].material_id != -1 {
     // Hit Color
@@ -278,14 +210,37 @@ fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
         attenuation *= materials[bounce_rec.material_id].albedo.xyz;
         rays[idx] = ray;
       }else {
-        attenuation *= vec3<f32>(0.7, 0.7, 0.7);
+        attenuation *= frame.sky;
         b_loop = false;
       }
       depth += 1;
     }
 
+    if frame.clamp > 0.0 {
+      attenuation = min(attenuation, vec3<f32>(frame.clamp));
+    }
     color = vec4<f32>(attenuation, 1.0);
 
+    // `DebugView`
+    switch frame.debug {
+      case 1u: {
+        color = vec4<f32>(rec[idx].normal * 0.5 + 0.5, 1.0);
+      }
+      case 2u: {
+        color = vec4<f32>(materials[rec[idx].material_id].albedo.xyz, 1.0);
+      }
+      case 3u: {
+        color = vec4<f32>(vec3<f32>(f32(depth) / f32(max(max_bounce, 1))), 1.0);
+      }
+      default: {}
+    }
+
+  }
+
+  var sum = color;
+  if frame.sample > 0u {
+    sum += accum[idx];
   }
-  textureStore(outputTexture, vec2<i32>(global_id.xy), to_srgb(color));
+  accum[idx] = sum;
+  textureStore(outputTexture, vec2<i32>(global_id.xy), to_srgb(sum / f32(frame.sample + 1u)));
 }